@@ -0,0 +1,94 @@
+//! Micro-benchmarks of single client operations and of parsing, without any threads involved.
+//! Run with `cargo bench --bench operations`.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use drizzly::client::Client;
+use drizzly::csv_ingestor::{csv_reader, parse_amount, CsvTransaction, TransactionType, Transactions};
+use drizzly::dispute::DisputePolicy;
+use drizzly::generator::{GeneratedRow, Generator, GeneratorConfig, OutputFormat};
+use drizzly::limits::WithdrawalLimits;
+use rust_decimal::Decimal;
+
+fn make_tx(tx_id: u32, tx_type: TransactionType, amount: Option<i64>) -> CsvTransaction {
+    CsvTransaction {
+        tx_type,
+        client_id: 1,
+        tx_id,
+        amount: amount.map(|amount| Decimal::new(amount, 4)),
+        timestamp: None,
+        currency: None,
+        reason: None,
+        conversion: None,
+    }
+}
+
+fn funded_client() -> Client {
+    let mut client = Client::default();
+    client.deposit(&make_tx(1, TransactionType::Deposit, Some(10_000_000))).unwrap();
+    client
+}
+
+fn client_operations(c: &mut Criterion) {
+    let mut group = c.benchmark_group("client");
+    let limits = WithdrawalLimits::default();
+    let policy = DisputePolicy::default();
+
+    let deposit = make_tx(2, TransactionType::Deposit, Some(12_345));
+    group.bench_function("deposit", |b| b.iter_batched_ref(
+        Client::default,
+        |client| client.deposit(black_box(&deposit)),
+        BatchSize::SmallInput,
+    ));
+
+    let withdrawal = make_tx(2, TransactionType::Withdrawal, Some(12_345));
+    group.bench_function("withdraw", |b| b.iter_batched_ref(
+        funded_client,
+        |client| client.withdraw(black_box(&withdrawal), &limits),
+        BatchSize::SmallInput,
+    ));
+
+    let (dispute, resolve) = (make_tx(1, TransactionType::Dispute, None), make_tx(1, TransactionType::Resolve, None));
+    group.bench_function("dispute_resolve", |b| b.iter_batched_ref(
+        funded_client,
+        |client| {
+            client.dispute(black_box(&dispute), &policy).unwrap();
+            client.resolve(black_box(&resolve)).unwrap();
+        },
+        BatchSize::SmallInput,
+    ));
+    group.finish();
+}
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parsing");
+
+    for amount in ["100", "50.023233", "1234567.8901"] {
+        group.bench_function(format!("parse_amount/{}", amount), |b| b.iter(|| parse_amount(black_box(amount))));
+    }
+
+    // the same rows drizzly-gen writes, deserialized from memory so only parsing is measured
+    let rows = 10_000;
+    let mut csv = String::from("type,client,tx,amount,timestamp\n");
+    for row in Generator::new(GeneratorConfig::default(), OutputFormat::Csv).take(rows) {
+        if let GeneratedRow::Valid(_) = row {
+            csv.push_str(&row.format(OutputFormat::Csv));
+            csv.push('\n');
+        }
+    }
+    group.throughput(Throughput::Elements(rows as u64));
+    group.bench_function("deserialize_rows", |b| b.iter(|| {
+        csv_reader(csv.as_bytes())
+            .deserialize::<CsvTransaction>()
+            .map(|row| row.unwrap().tx_id)
+            .fold(0u64, |sum, tx_id| sum + u64::from(tx_id))
+    }));
+    group.bench_function("read_rows_fast_path", |b| b.iter(|| {
+        Transactions::new(csv.as_bytes())
+            .map(|row| row.unwrap().tx_id)
+            .fold(0u64, |sum, tx_id| sum + u64::from(tx_id))
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, client_operations, parsing);
+criterion_main!(benches);
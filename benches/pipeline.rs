@@ -0,0 +1,126 @@
+//! End-to-end benchmarks: CSV file → `read_csv` → `Dispatcher` → workers, at several worker counts, client
+//! skews, batch sizes and parsing modes, plus the latency of a single transaction through the threaded engine.
+//! Run with `cargo bench --bench pipeline 2>/dev/null`, workers log every rejected transaction to STD err.
+
+use std::fs::File;
+use std::path::PathBuf;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use drizzly::csv_ingestor::{CsvTransaction, TransactionType};
+use drizzly::engine::Engine;
+use drizzly::generator::{generate, GeneratorConfig, OutputFormat};
+use drizzly::parallel_ingestor::ParallelRead;
+use rust_decimal::Decimal;
+
+const ROWS: u64 = 100_000;
+
+/// A generated input file, removed once the benchmark is done
+struct Input(PathBuf);
+
+impl Input {
+    fn generate(skew: f64) -> Self {
+        let path = std::env::temp_dir().join(format!("drizzly-bench-{}-{}.csv", std::process::id(), skew));
+        // no chargebacks, locked clients would turn most of their rows into rejections
+        let config = GeneratorConfig { skew, chargeback_rate: 0.0, ..GeneratorConfig::default() };
+        let file = File::create(&path).expect("Could not create benchmark input");
+        generate(config, OutputFormat::Csv, None, Some(ROWS), file, None).expect("Could not generate benchmark input");
+        Input(path)
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().expect("temp paths are UTF-8")
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pipeline");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ROWS));
+
+    // uniform activity, and most rows on a handful of hot clients
+    for skew in [0.0, 1.5] {
+        let input = Input::generate(skew);
+        for workers in [1, 2, 4, 8] {
+            group.bench_with_input(BenchmarkId::new(format!("skew-{}", skew), workers), &workers, |b, &workers| {
+                b.iter(|| {
+                    let report = Engine::builder().workers(workers).source(input.path()).build().shutdown();
+                    assert!(report.errors.is_empty() && report.ingestion.iter().all(Result::is_ok));
+                    report.clients.len()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn batching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch_size");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ROWS));
+
+    let input = Input::generate(0.0);
+    for batch_size in [1, 16, 256, 4096] {
+        group.bench_with_input(BenchmarkId::from_parameter(batch_size), &batch_size, |b, &batch_size| {
+            b.iter(|| Engine::builder().workers(4).batch_size(batch_size).source(input.path()).build().shutdown())
+        });
+    }
+    group.finish();
+}
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parsing");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ROWS));
+
+    let input = Input::generate(0.0);
+    let parallel = ParallelRead { parsers: 4, chunk_size: 256 * 1024, mmap: false };
+    for (name, parallel_read) in [
+        ("sequential", None),
+        ("parallel", Some(parallel)),
+        ("parallel-mmap", Some(ParallelRead { mmap: true, ..parallel })),
+    ] {
+        group.bench_function(name, |b| b.iter(|| {
+            let mut builder = Engine::builder().workers(4).source(input.path());
+            if let Some(parallel_read) = parallel_read {
+                builder = builder.parallel_read(parallel_read);
+            }
+            builder.build().shutdown()
+        }));
+    }
+    group.finish();
+}
+
+fn latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("latency");
+
+    for workers in [1, 4] {
+        let engine = Engine::builder().workers(workers).build();
+        let mut tx_id = 0;
+        group.bench_with_input(BenchmarkId::new("submit_flush", workers), &workers, |b, _| {
+            b.iter(|| {
+                tx_id += 1;
+                engine.submit(CsvTransaction {
+                    tx_type: TransactionType::Deposit,
+                    client_id: (tx_id % 100) as u16,
+                    tx_id,
+                    amount: Some(Decimal::ONE),
+                    timestamp: None,
+                    currency: None,
+                    reason: None,
+                    conversion: None,
+                }).unwrap();
+                engine.flush().unwrap();
+            })
+        });
+        engine.shutdown();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput, batching, parsing, latency);
+criterion_main!(benches);
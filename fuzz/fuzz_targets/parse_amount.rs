@@ -0,0 +1,13 @@
+#![no_main]
+
+use drizzly::csv_ingestor::parse_amount;
+use libfuzzer_sys::fuzz_target;
+
+// Every accepted amount has at most 4 decimal places and parses back to itself from its own output
+fuzz_target!(|data: &[u8]| {
+    let Ok(s) = std::str::from_utf8(data) else { return };
+    let Ok(amount) = parse_amount(s) else { return };
+
+    assert!(amount.scale() <= 4, "{:?} parsed to {} with more than 4 decimal places", s, amount);
+    assert_eq!(parse_amount(&amount.to_string()).ok(), Some(amount), "{:?} does not round-trip", s);
+});
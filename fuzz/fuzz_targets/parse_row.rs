@@ -0,0 +1,18 @@
+#![no_main]
+
+use drizzly::csv_ingestor::{csv_reader, CsvTransaction, Transactions};
+use libfuzzer_sys::fuzz_target;
+
+// Any bytes are read as a CSV file the way `read_csv` does, rows are either rejected or parsed with 4-dp amounts,
+// and `Transactions` (fast path or not) reads every row as serde does, errors included
+fuzz_target!(|data: &[u8]| {
+    let deserialized: Vec<_> = csv_reader(data).deserialize::<CsvTransaction>().map(|row| row.map_err(|e| e.to_string())).collect();
+    let read: Vec<_> = Transactions::new(data).map(|row| row.map_err(|e| e.to_string())).collect();
+    assert_eq!(read, deserialized, "Transactions and serde disagree");
+
+    for csv_transaction in deserialized.into_iter().flatten() {
+        if let Some(amount) = csv_transaction.amount {
+            assert!(amount.scale() <= 4, "amount {} kept more than 4 decimal places", amount);
+        }
+    }
+});
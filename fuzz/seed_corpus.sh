@@ -0,0 +1,18 @@
+#!/bin/sh
+# Build the seed corpus from the CSV fixtures: whole files for row parsing, their amount column for amount parsing.
+# Run from anywhere, then `cargo fuzz run parse_row` or `cargo fuzz run parse_amount` from the repository root.
+set -eu
+
+fuzz_dir=$(cd "$(dirname "$0")" && pwd)
+fixtures="$fuzz_dir/../tests"
+
+mkdir -p "$fuzz_dir/corpus/parse_row" "$fuzz_dir/corpus/parse_amount"
+
+for fixture in "$fixtures"/*.csv; do
+    cp "$fixture" "$fuzz_dir/corpus/parse_row/$(basename "$fixture")"
+done
+
+# amount is the 4th column of the transaction files, one seed per distinct value
+cat "$fixtures"/*.csv | cut -d, -f4 | tr -d ' \r' | grep -E '^[0-9.-]+$' | sort -u | while read -r amount; do
+    printf '%s' "$amount" > "$fuzz_dir/corpus/parse_amount/$amount"
+done
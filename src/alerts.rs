@@ -0,0 +1,96 @@
+use std::fmt;
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+use crate::error::ApplicationError;
+
+/// What the engine did about an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertKind {
+    /// Transaction applied but worth a look
+    Flag,
+    /// Transaction refused
+    Deny,
+    /// Account frozen automatically
+    Freeze,
+    /// Invariant broken, raised in audit mode
+    Violation,
+    /// Dispute exceeded the available balance and was settled by the overdraft policy
+    Overdraft,
+}
+
+impl fmt::Display for AlertKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AlertKind::Flag => "flag",
+            AlertKind::Deny => "deny",
+            AlertKind::Freeze => "freeze",
+            AlertKind::Violation => "violation",
+            AlertKind::Overdraft => "overdraft",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Something workers report for review, kept apart from errors and the balances output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub client_id: u16,
+    pub tx_id: u32,
+    pub kind: AlertKind,
+    /// Name of the rule or policy that raised it
+    pub source: String,
+    pub detail: String,
+}
+
+/// Drain alerts into `writer` as CSV until every sender is dropped, returns how many were written
+pub fn write_alerts(alert_receiver: Receiver<Alert>, writer: impl Write) -> Result<usize, ApplicationError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    let io_error = |e: csv::Error| ApplicationError::Other(format!("Could not write alert: {}", e));
+
+    csv_writer.write_record(["client", "tx", "kind", "source", "detail"]).map_err(io_error)?;
+
+    let mut written = 0;
+    for alert in alert_receiver {
+        csv_writer.write_record([
+            alert.client_id.to_string(),
+            alert.tx_id.to_string(),
+            alert.kind.to_string(),
+            alert.source,
+            alert.detail,
+        ]).map_err(io_error)?;
+        written += 1;
+    }
+
+    csv_writer.flush()
+        .map_err(|e| ApplicationError::Other(format!("Could not write alerts: {}", e)))?;
+    Ok(written)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use super::*;
+
+    #[test]
+    fn test_write_alerts() {
+        let (alert_sender, alert_receiver) = channel();
+        alert_sender.send(Alert {
+            client_id: 7,
+            tx_id: 42,
+            kind: AlertKind::Flag,
+            source: "repeated-disputes".to_string(),
+            detail: "3 disputes raised, threshold 3".to_string(),
+        }).unwrap();
+        drop(alert_sender);
+
+        let mut output = Vec::new();
+        let written = write_alerts(alert_receiver, &mut output).unwrap();
+
+        assert_eq!(written, 1);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,kind,source,detail\n7,42,flag,repeated-disputes,\"3 disputes raised, threshold 3\"\n"
+        );
+    }
+}
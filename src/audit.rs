@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use rust_decimal::Decimal;
+use crate::client::{Balance, Client};
+use crate::csv_ingestor::CsvTransaction;
+use crate::currency::Currency;
+use crate::ledger::LedgerAccount;
+use crate::lifecycle::AccountState;
+
+pub const NON_NEGATIVE_HELD: &str = "non_negative_held";
+pub const NON_NEGATIVE_AVAILABLE: &str = "non_negative_available";
+pub const LOCKED_ACCOUNT_UNCHANGED: &str = "locked_account_unchanged";
+pub const TOTAL_MATCHES_POSTINGS: &str = "total_matches_postings";
+
+/// Client state captured before an operation, to compare against afterwards
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientSnapshot {
+    pub state: AccountState,
+    pub balances: BTreeMap<Currency, Balance>,
+    /// Postings in the client's ledger, those after them are the operation's
+    pub postings: usize,
+}
+
+impl ClientSnapshot {
+    pub fn of(client: &Client) -> Self {
+        Self { state: client.state, balances: client.balances(), postings: client.ledger.postings().len() }
+    }
+}
+
+/// An invariant broken by applying `tx`, with the balances around it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub invariant: &'static str,
+    pub currency: Currency,
+    pub before: Balance,
+    pub after: Balance,
+    pub tx: CsvTransaction,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} broken in {} by {:?} tx {} (amount {}, timestamp {}): \
+             before available {:.4} held {:.4} total {:.4}, after available {:.4} held {:.4} total {:.4}",
+            self.invariant,
+            self.currency,
+            self.tx.tx_type,
+            self.tx.tx_id,
+            self.tx.amount.map_or_else(|| "none".to_string(), |a| a.to_string()),
+            self.tx.timestamp.map_or_else(|| "none".to_string(), |t| t.to_rfc3339()),
+            self.before.available, self.before.held, self.before.total,
+            self.after.available, self.after.held, self.after.total,
+        )
+    }
+}
+
+/// Compare a client against its snapshot from before `tx` was applied.
+/// Negative balances only count when `tx` made them worse, so one bad row is reported once.
+/// Available may go down to `-overdraft_limit` before it counts as negative.
+pub fn check_invariants(before: &ClientSnapshot, client: &Client, tx: &CsvTransaction, overdraft_limit: Decimal) -> Vec<Violation> {
+    let after = client.balances();
+    let mut violations = Vec::new();
+
+    let mut currencies: Vec<&Currency> = before.balances.keys().chain(after.keys()).collect();
+    currencies.sort();
+    currencies.dedup();
+
+    for currency in currencies {
+        let was = before.balances.get(currency).copied().unwrap_or_default();
+        let is = after.get(currency).copied().unwrap_or_default();
+        let mut broken = |invariant| violations.push(Violation {
+            invariant,
+            currency: currency.clone(),
+            before: was,
+            after: is,
+            tx: tx.clone(),
+        });
+
+        if is.held < Decimal::ZERO && is.held < was.held {
+            broken(NON_NEGATIVE_HELD);
+        }
+        if is.available < -overdraft_limit && is.available < was.available {
+            broken(NON_NEGATIVE_AVAILABLE);
+        }
+        if before.state == AccountState::Locked && is != was {
+            broken(LOCKED_ACCOUNT_UNCHANGED);
+        }
+
+        // the total worked out from the postings the operation made, against available + held from the running balances
+        let moved: Decimal = client.ledger.postings().get(before.postings..).unwrap_or_default().iter()
+            .filter(|posting| &posting.currency == currency)
+            .map(|posting| posting.amount * (client_side(posting.to) - client_side(posting.from)))
+            .sum();
+        if was.total + moved != is.available + is.held {
+            broken(TOTAL_MATCHES_POSTINGS);
+        }
+    }
+
+    violations
+}
+
+/// 1 for the client's own accounts, 0 for the outside ones, so a posting between two of the client's accounts moves nothing
+fn client_side(account: LedgerAccount) -> Decimal {
+    match account {
+        LedgerAccount::ClientAvailable | LedgerAccount::ClientHeld => Decimal::ONE,
+        LedgerAccount::Settlement | LedgerAccount::ChargebackLoss => Decimal::ZERO,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+    use crate::csv_ingestor::TransactionType;
+    use crate::dispute::{DisputePolicy, OverdraftPolicy};
+    use crate::limits::WithdrawalLimits;
+
+
+    #[test]
+    fn test_clean_operations_pass() {
+        let mut client = Client::default();
+
+        let deposit = tx(TransactionType::Deposit, 1, 1).with_amount(100);
+        let before = ClientSnapshot::of(&client);
+        client.deposit(&deposit).unwrap();
+        assert!(check_invariants(&before, &client, &deposit, Decimal::ZERO).is_empty());
+
+        let withdrawal = tx(TransactionType::Withdrawal, 1, 2).with_amount(40);
+        let before = ClientSnapshot::of(&client);
+        client.withdraw(&withdrawal, &WithdrawalLimits::default()).unwrap();
+        assert!(check_invariants(&before, &client, &withdrawal, Decimal::ZERO).is_empty());
+    }
+
+    #[test]
+    fn test_dispute_of_withdrawn_funds_is_reported() {
+        // the second README example: funds are withdrawn before their deposit is disputed
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(100)).unwrap();
+        client.withdraw(&tx(TransactionType::Withdrawal, 1, 2).with_amount(60), &WithdrawalLimits::default()).unwrap();
+
+        let dispute = tx(TransactionType::Dispute, 1, 1);
+        let before = ClientSnapshot::of(&client);
+        client.dispute(&dispute, &DisputePolicy::default()).unwrap();
+
+        let violations = check_invariants(&before, &client, &dispute, Decimal::ZERO);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].invariant, NON_NEGATIVE_AVAILABLE);
+        assert_eq!(violations[0].before.available, Decimal::from(40));
+        assert_eq!(violations[0].after.available, Decimal::from(-60));
+        assert!(violations[0].to_string().contains("Dispute tx 1"));
+    }
+
+    #[test]
+    fn test_locked_account_change_is_reported() {
+        let mut client = Client { state: AccountState::Locked, ..Default::default() };
+
+        // client operations refuse this, a posting made behind their back must still be caught
+        let deposit = tx(TransactionType::Deposit, 1, 1).with_amount(10);
+        let before = ClientSnapshot::of(&client);
+        assert!(client.deposit(&deposit).is_err());
+        client.ledger.post(1, LedgerAccount::Settlement, LedgerAccount::ClientAvailable, Currency::default(), Decimal::from(10));
+
+        let invariants: Vec<&str> = check_invariants(&before, &client, &deposit, Decimal::ZERO).iter().map(|v| v.invariant).collect();
+        assert_eq!(invariants, vec![LOCKED_ACCOUNT_UNCHANGED]);
+    }
+
+    #[test]
+    fn test_total_drift_is_reported() {
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(100)).unwrap();
+
+        let withdrawal = tx(TransactionType::Withdrawal, 1, 2).with_amount(40);
+        let before = ClientSnapshot::of(&client);
+        client.withdraw(&withdrawal, &WithdrawalLimits::default()).unwrap();
+        assert!(check_invariants(&before, &client, &withdrawal, Decimal::ZERO).is_empty());
+
+        // a total that drifted from its postings before the withdrawal, the withdrawal's own postings cannot explain the new one
+        let mut drifted = before.clone();
+        drifted.balances.get_mut(&Currency::default()).unwrap().total += Decimal::ONE;
+        let invariants: Vec<&str> = check_invariants(&drifted, &client, &withdrawal, Decimal::ZERO).iter().map(|v| v.invariant).collect();
+        assert_eq!(invariants, vec![TOTAL_MATCHES_POSTINGS]);
+
+        // nor can postings left out of the count
+        let uncounted = ClientSnapshot { postings: before.postings + 1, ..before };
+        let invariants: Vec<&str> = check_invariants(&uncounted, &client, &withdrawal, Decimal::ZERO).iter().map(|v| v.invariant).collect();
+        assert_eq!(invariants, vec![TOTAL_MATCHES_POSTINGS]);
+    }
+
+    #[test]
+    fn test_overdraft_limit_is_not_reported() {
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(100)).unwrap();
+        client.withdraw(&tx(TransactionType::Withdrawal, 1, 2).with_amount(60), &WithdrawalLimits::default()).unwrap();
+
+        let overdraft_limit = Decimal::from(100);
+        let policy = DisputePolicy { overdraft: OverdraftPolicy::Collections { overdraft_limit }, ..DisputePolicy::default() };
+        let dispute = tx(TransactionType::Dispute, 1, 1);
+        let before = ClientSnapshot::of(&client);
+        client.dispute(&dispute, &policy).unwrap();
+
+        assert_eq!(client.balance(&Currency::default()).available, Decimal::from(-60));
+        assert!(check_invariants(&before, &client, &dispute, overdraft_limit).is_empty());
+    }
+}
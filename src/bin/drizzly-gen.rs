@@ -0,0 +1,120 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use drizzly::error::ApplicationError;
+use drizzly::generator::{check_expected, generate, parse_size, GeneratorConfig, OutputFormat};
+
+const USAGE: &str = "Usage: cargo run --bin drizzly-gen -- (--size <bytes[K|M|G]> | --rows <count>) [--out <path>] \
+[--format csv|jsonl] [--clients <count>] [--skew <exponent>] [--dispute-rate <ratio>] [--chargeback-rate <ratio>] \
+[--malformed-rate <ratio>] [--seed <number>] [--expected <path>, CSV only, with --rows <count> of at most 1000000]";
+
+/// Everything taken from the command line
+struct GenArgs {
+    config: GeneratorConfig,
+    format: OutputFormat,
+    target_bytes: Option<u64>,
+    max_rows: Option<u64>,
+    out_path: Option<String>,
+    expected_path: Option<String>,
+}
+
+fn flag_value<'a>(iter: &mut impl Iterator<Item = &'a String>, flag: &str) -> Result<&'a String, ApplicationError> {
+    iter.next()
+        .ok_or_else(|| ApplicationError::InvalidConfiguration(format!("{} needs a value", flag)))
+}
+
+fn parse_flag<T: std::str::FromStr>(value: &str, flag: &str) -> Result<T, ApplicationError>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e| ApplicationError::InvalidConfiguration(format!("{}: {}", flag, e)))
+}
+
+fn parse_rate(value: &str, flag: &str) -> Result<f64, ApplicationError> {
+    let rate: f64 = parse_flag(value, flag)?;
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(ApplicationError::InvalidConfiguration(format!("{}: {} is not between 0 and 1", flag, rate)))
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<GenArgs, ApplicationError> {
+    let mut config = GeneratorConfig::default();
+    let mut format = OutputFormat::default();
+    let mut target_bytes = None;
+    let mut max_rows = None;
+    let mut out_path = None;
+    let mut expected_path = None;
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--size" => target_bytes = Some(parse_size(flag_value(&mut iter, arg)?)?),
+            "--rows" => max_rows = Some(parse_flag(flag_value(&mut iter, arg)?, arg)?),
+            "--out" => out_path = Some(flag_value(&mut iter, arg)?.to_string()),
+            "--format" => format = flag_value(&mut iter, arg)?.parse()?,
+            "--clients" => config.clients = parse_flag(flag_value(&mut iter, arg)?, arg)?,
+            "--skew" => config.skew = parse_flag(flag_value(&mut iter, arg)?, arg)?,
+            "--dispute-rate" => config.dispute_rate = parse_rate(flag_value(&mut iter, arg)?, arg)?,
+            "--chargeback-rate" => config.chargeback_rate = parse_rate(flag_value(&mut iter, arg)?, arg)?,
+            "--malformed-rate" => config.malformed_rate = parse_rate(flag_value(&mut iter, arg)?, arg)?,
+            "--seed" => config.seed = parse_flag(flag_value(&mut iter, arg)?, arg)?,
+            "--expected" => expected_path = Some(flag_value(&mut iter, arg)?.to_string()),
+            other => return Err(ApplicationError::InvalidConfiguration(format!("unknown argument {}", other))),
+        }
+    }
+
+    if target_bytes.is_none() && max_rows.is_none() {
+        return Err(ApplicationError::InvalidConfiguration("--size or --rows is required".to_string()));
+    }
+    if config.clients == 0 {
+        return Err(ApplicationError::InvalidConfiguration("--clients must be at least 1".to_string()));
+    }
+    if expected_path.is_some() {
+        check_expected(format, max_rows)?;
+    }
+    Ok(GenArgs { config, format, target_bytes, max_rows, out_path, expected_path })
+}
+
+fn create(path: &str) -> Result<BufWriter<File>, ApplicationError> {
+    File::create(path)
+        .map(BufWriter::new)
+        .map_err(|e| ApplicationError::CouldNotOpenFile(format!("{}: {}", path, e)))
+}
+
+fn run(args: GenArgs) -> Result<(), ApplicationError> {
+    let GenArgs { config, format, target_bytes, max_rows, out_path, expected_path } = args;
+
+    let writer: Box<dyn Write> = match out_path {
+        Some(path) => Box::new(create(&path)?),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let mut expected = expected_path.as_deref().map(create).transpose()?;
+
+    let stats = generate(config, format, target_bytes, max_rows, writer, expected.as_mut().map(|w| w as &mut dyn Write))?;
+    if let Some(mut expected) = expected {
+        expected.flush()
+            .map_err(|e| ApplicationError::Other(format!("Could not write expected balances: {}", e)))?;
+    }
+
+    eprintln!("{} rows ({} malformed), {} bytes", stats.rows, stats.malformed, stats.bytes);
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let args = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
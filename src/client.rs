@@ -1,10 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Write;
 use std::sync::{Arc, RwLock};
+use chrono::{DateTime, NaiveTime, Utc};
 use rust_decimal::Decimal;
 use rust_decimal::prelude::{Zero};
-use crate::csv_ingestor::CsvTransaction;
+use crate::csv_ingestor::{CsvTransaction, TransactionType};
+use crate::currency::Currency;
+use crate::dispute::{AbuseThresholds, DisputePolicy, DisputeStats, ExpiryAction, OpenDispute, OverdraftOutcome, OverdraftPolicy};
 use crate::error::ApplicationError;
-use crate::error::ApplicationError::InsufficientAvailableBalanceForWithdrawal;
+use crate::error::ApplicationError::{DisputeOfUnknownTransaction, DisputeWindowExpired, InsufficientAvailableBalanceForDispute, InsufficientAvailableBalanceForRefund, InsufficientAvailableBalanceForWithdrawal, InvalidDisputeAmount, InvalidRefundAmount, LimitExceeded, MissingAuditReason, NotUnderDispute, RefundExceedsDeposit, RefundOfDisputedTransaction, RefundOfUnknownDeposit, TransactionNotAllowed};
+use crate::ledger::{Ledger, LedgerAccount};
+use crate::lifecycle::{AccountState, AuditEntry};
+use crate::limits::{WithdrawalLimits, DAILY_WITHDRAWAL_TOTAL, MAX_SINGLE_WITHDRAWAL, WITHDRAWAL_VELOCITY};
 
 /// a thread-safe mutable hashmap which holds client-id vs state
 pub type GlobalClientsMap = Arc<RwLock<HashMap<u16, Client>>>;
@@ -13,40 +20,130 @@ pub fn new_clients_map() -> GlobalClientsMap {
     Arc::new(RwLock::new(HashMap::new()))
 }
 
+/// Write every client's balances the way drizzly prints them, ordered by client id.
+/// One row per currency, clients that never moved funds get a zero row in `default_currency`.
+pub fn write_balances(clients: &HashMap<u16, Client>, default_currency: &Currency, mut writer: impl Write) -> Result<(), ApplicationError> {
+    let io_error = |e: std::io::Error| ApplicationError::Other(format!("Could not write balances: {}", e));
 
-/// Client holds client state include tx history
-#[derive(Debug, Default)]
-pub struct Client {
+    let mut client_ids: Vec<&u16> = clients.keys().collect();
+    client_ids.sort();
+
+    writeln!(writer, "client,currency,available,held,total,locked,state").map_err(io_error)?;
+    for id in client_ids {
+        let client = &clients[id];
+        let mut balances = client.balances();
+        if balances.is_empty() {
+            balances.insert(default_currency.clone(), Balance::default());
+        }
+
+        for (currency, balance) in balances {
+            // output all amounts in 4 decimal places
+            writeln!(writer, "{},{},{:.4},{:.4},{:.4},{},{}",
+                     id, currency, balance.available, balance.held, balance.total,
+                     client.state == AccountState::Locked, client.state
+            ).map_err(io_error)?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Funds a client holds in one currency, a view derived from the client's ledger
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Balance {
     pub available: Decimal,
     pub held: Decimal,
     pub total: Decimal,
-    pub locked: bool,
+}
+
+/// Client holds client state include tx history
+#[derive(Debug, Clone, Default)]
+pub struct Client {
+    /// Every movement of funds as balanced postings, balances are derived from it
+    pub ledger: Ledger,
+    pub state: AccountState,
 
     pub tx_history: HashMap<u32, CsvTransaction>,
+    /// Open disputes keyed by the disputed tx id, oldest first, a tx may be disputed in several portions
+    pub disputes: HashMap<u32, Vec<OpenDispute>>,
+    /// Amount charged back per tx, it can no longer be disputed
+    pub charged_back: HashMap<u32, Decimal>,
+    /// Amount refunded per deposit tx
+    pub refunded: HashMap<u32, Decimal>,
+    /// Every change of `state`, oldest first
+    pub audit_log: Vec<AuditEntry>,
+    /// Timestamped withdrawals still relevant to the daily and velocity limits
+    pub recent_withdrawals: VecDeque<(DateTime<Utc>, Currency, Decimal)>,
+    /// Tx id of the latest deposit
+    pub last_deposit: Option<u32>,
+    /// Number of disputes ever raised by the client
+    pub disputes_raised: u32,
+    /// Amount ever deposited per currency
+    pub deposited: BTreeMap<Currency, Decimal>,
 }
 
 
 impl Client {
-     pub fn deposit(&mut self, tx: &CsvTransaction) {
+    /// Balance in `currency`, zero when the client never used it
+    pub fn balance(&self, currency: &Currency) -> Balance {
+        let available = self.ledger.balance(LedgerAccount::ClientAvailable, currency);
+        let held = self.ledger.balance(LedgerAccount::ClientHeld, currency);
+        Balance { available, held, total: available + held }
+    }
+
+    /// Balances per currency, a currency appears once the client first moves funds in it
+    pub fn balances(&self) -> BTreeMap<Currency, Balance> {
+        self.ledger.accounts()
+            .filter(|((account, _), _)| matches!(account, LedgerAccount::ClientAvailable | LedgerAccount::ClientHeld))
+            .map(|((_, currency), _)| (currency.clone(), self.balance(currency)))
+            .collect()
+    }
+
+    /// Refuse an operation of `tx_type` the account state does not allow, see `AccountState::allows`
+    fn check_allowed(&self, tx_type: TransactionType, tx: &CsvTransaction) -> Result<(), ApplicationError> {
+        if self.state.allows(&tx_type) {
+            Ok(())
+        } else {
+            Err(TransactionNotAllowed(tx.client_id, tx.tx_id, self.state))
+        }
+    }
+
+    // rows without a currency are booked in the default one
+    fn post(&mut self, tx_id: u32, from: LedgerAccount, to: LedgerAccount, currency: &Option<Currency>, amount: Decimal) {
+        self.ledger.post(tx_id, from, to, currency.clone().unwrap_or_default(), amount);
+    }
+
+    /// Operations refuse what the account state does not allow, a locked account stays as it is
+    pub fn deposit(&mut self, tx: &CsvTransaction) -> Result<(), ApplicationError> {
+        self.check_allowed(TransactionType::Deposit, tx)?;
         match tx.amount {
             Some(amount) => {
-                self.available += amount;
-                self.total += amount;
+                self.post(tx.tx_id, LedgerAccount::Settlement, LedgerAccount::ClientAvailable, &tx.currency, amount);
                 self.tx_history.insert(tx.tx_id, tx.clone());
+                self.last_deposit = Some(tx.tx_id);
+                *self.deposited.entry(tx.currency.clone().unwrap_or_default()).or_default() += amount;
+                self.leave_collections(tx.tx_id, tx.timestamp);
             }
             None => {
                 // Do nothing if amount is None
             }
         }
+        Ok(())
     }
 
-    pub fn withdraw(&mut self, tx: &CsvTransaction) -> Result<(), ApplicationError> {
+    /// Withdrawals must stay within the client's `limits` and available balance
+    pub fn withdraw(&mut self, tx: &CsvTransaction, limits: &WithdrawalLimits) -> Result<(), ApplicationError> {
+        self.check_allowed(TransactionType::Withdrawal, tx)?;
         let amount = tx.amount.unwrap_or_else(Decimal::zero);
+        self.check_limits(tx, amount, limits)?;
+        let currency = tx.currency.clone().unwrap_or_default();
 
-        if self.available >= amount {
-            self.available -= amount;
-            self.total -= amount;
+        if self.balance(&currency).available >= amount {
+            self.post(tx.tx_id, LedgerAccount::ClientAvailable, LedgerAccount::Settlement, &tx.currency, amount);
             self.tx_history.insert(tx.tx_id, tx.clone());
+            if let Some(timestamp) = tx.timestamp {
+                self.record_withdrawal(timestamp, tx.currency.clone().unwrap_or_default(), amount, limits);
+            }
             Ok(())
         } else {
             Err(InsufficientAvailableBalanceForWithdrawal(
@@ -55,65 +152,295 @@ impl Client {
         }
     }
 
+    fn check_limits(&self, tx: &CsvTransaction, amount: Decimal, limits: &WithdrawalLimits) -> Result<(), ApplicationError> {
+        if let Some(max_single) = limits.max_single
+            && amount > max_single
+        {
+            return Err(LimitExceeded(tx.client_id, tx.tx_id, MAX_SINGLE_WITHDRAWAL));
+        }
+
+        // the remaining rules need to know when the withdrawal happens
+        let Some(now) = tx.timestamp else {
+            return Ok(());
+        };
+
+        if let Some(daily_total) = limits.daily_total {
+            let currency = tx.currency.clone().unwrap_or_default();
+            let day_start = now.date_naive().and_time(NaiveTime::MIN).and_utc();
+            let withdrawn_today: Decimal = self.recent_withdrawals.iter()
+                .filter(|(at, c, _)| *at >= day_start && *at <= now && *c == currency)
+                .map(|(_, _, amount)| *amount)
+                .sum();
+            if withdrawn_today + amount > daily_total {
+                return Err(LimitExceeded(tx.client_id, tx.tx_id, DAILY_WITHDRAWAL_TOTAL));
+            }
+        }
+
+        if let Some((max_count, window)) = limits.velocity {
+            let in_window = self.recent_withdrawals.iter()
+                .filter(|(at, _, _)| *at > now - window && *at <= now)
+                .count();
+            if in_window >= max_count {
+                return Err(LimitExceeded(tx.client_id, tx.tx_id, WITHDRAWAL_VELOCITY));
+            }
+        }
+
+        Ok(())
+    }
+
+    // keep only what the daily and velocity rules can still look at
+    fn record_withdrawal(&mut self, at: DateTime<Utc>, currency: Currency, amount: Decimal, limits: &WithdrawalLimits) {
+        let day_start = at.date_naive().and_time(NaiveTime::MIN).and_utc();
+        let window_start = limits.velocity.map_or(day_start, |(_, window)| at - window);
+        let cutoff = day_start.min(window_start);
+
+        self.recent_withdrawals.retain(|(at, _, _)| *at >= cutoff);
+        self.recent_withdrawals.push_back((at, currency, amount));
+    }
+
+    /// A refund returns part or all of the deposit referenced by the tx id, the rest when no amount is given.
+    /// Funds are taken from available in the deposit's currency: available and total decrease.
+    /// Cumulative refunds never exceed the deposit and a disputed deposit cannot be refunded.
+    /// Transactions that do not exist or are not deposits are rejected.
+    pub fn refund(&mut self, refund: &CsvTransaction) -> Result<(), ApplicationError> {
+        self.check_allowed(TransactionType::Refund, refund)?;
+        let tx_id = refund.tx_id;
+        // a deposit without an amount has nothing to return
+        let Some((deposit, deposited)) = self.tx_history.get(&tx_id)
+            .filter(|tx| tx.tx_type == TransactionType::Deposit)
+            .and_then(|tx| Some((tx, tx.amount?)))
+        else {
+            return Err(RefundOfUnknownDeposit(refund.client_id, tx_id));
+        };
+
+        if self.disputes.contains_key(&tx_id) {
+            return Err(RefundOfDisputedTransaction(refund.client_id, tx_id));
+        }
+
+        let remaining = deposited - self.charged_back.get(&tx_id).copied().unwrap_or_default()
+            - self.refunded.get(&tx_id).copied().unwrap_or_default();
+        if refund.amount.is_some_and(|amount| amount <= Decimal::ZERO) {
+            return Err(InvalidRefundAmount(refund.client_id, tx_id));
+        }
+        let amount = refund.amount.unwrap_or(remaining);
+        // nothing left to refund of the deposit counts as exceeding it
+        if amount <= Decimal::ZERO || amount > remaining {
+            return Err(RefundExceedsDeposit(refund.client_id, tx_id));
+        }
+
+        let currency = deposit.currency.clone().unwrap_or_default();
+        if self.balance(&currency).available < amount {
+            return Err(InsufficientAvailableBalanceForRefund(refund.client_id, tx_id));
+        }
+
+        self.post(tx_id, LedgerAccount::ClientAvailable, LedgerAccount::Settlement, &Some(currency), amount);
+        *self.refunded.entry(tx_id).or_default() += amount;
+        Ok(())
+    }
+
     /// A dispute represents a client's claim that a transaction was erroneous and should be reversed.
-    /// Funds should be held: available decreases, held increases, total remains the same.
-    /// Ignore non-existing transactions.
-    pub fn dispute(&mut self, tx_id: u32) {
-        if let Some(tx) = self.tx_history.get(&tx_id) {
-            //eprintln!("tx is {:#?}", tx);
-            match tx.amount {
-                Some(amount) => {
-                    self.available -= amount;
-                    self.held += amount;
+    /// Funds should be held in the original transaction's currency: available decreases, held increases, total remains the same.
+    /// A dispute row carrying an amount disputes only that portion, otherwise whatever is not yet disputed.
+    /// Transactions that do not exist, have no amount or are already fully under dispute are rejected.
+    /// When both rows carry a timestamp, a dispute raised more than the policy's window after the original transaction is rejected.
+    /// A dispute for more than is available is settled by the policy's overdraft rule, whose outcome is returned.
+    pub fn dispute(&mut self, dispute: &CsvTransaction, policy: &DisputePolicy) -> Result<Option<OverdraftOutcome>, ApplicationError> {
+        self.check_allowed(TransactionType::Dispute, dispute)?;
+        let tx_id = dispute.tx_id;
+        // nothing to hold for an unknown transaction or one without an amount
+        let (Some(tx), Some(undisputed)) = (self.tx_history.get(&tx_id), self.undisputed_amount(tx_id)) else {
+            return Err(DisputeOfUnknownTransaction(dispute.client_id, tx_id));
+        };
+
+        if let (Some(window), Some(raised_at), Some(original_at)) = (policy.window, dispute.timestamp, tx.timestamp)
+            && raised_at - original_at > window
+        {
+            return Err(DisputeWindowExpired(dispute.client_id, tx_id));
+        }
+
+        let amount = match dispute.amount {
+            Some(amount) if amount <= Decimal::ZERO || amount > undisputed => {
+                return Err(InvalidDisputeAmount(dispute.client_id, tx_id));
+            }
+            Some(amount) => amount,
+            None if undisputed <= Decimal::ZERO => {
+                return Err(InvalidDisputeAmount(dispute.client_id, tx_id));
+            }
+            None => undisputed,
+        };
+        let currency = tx.currency.clone().unwrap_or_default();
+        let available = self.balance(&currency).available;
+        let (held, outcome) = if amount <= available {
+            (amount, None)
+        } else {
+            match policy.overdraft {
+                OverdraftPolicy::AllowNegative => (amount, Some(OverdraftOutcome::AllowedNegative { requested: amount, available })),
+                OverdraftPolicy::Reject => return Err(InsufficientAvailableBalanceForDispute(dispute.client_id, tx_id)),
+                OverdraftPolicy::HoldAvailable => {
+                    let held = available.max(Decimal::ZERO);
+                    (held, Some(OverdraftOutcome::HeldAvailable { requested: amount, held }))
                 }
-                None => {
-                    eprintln!("WARNING: referenced tx for dispute had no amount!!!, {}:{}", tx.client_id, tx.tx_id );
+                OverdraftPolicy::Collections { overdraft_limit } => {
+                    let held = amount.min((available + overdraft_limit).max(Decimal::ZERO));
+                    (held, Some(OverdraftOutcome::Collections { requested: amount, held }))
                 }
             }
-        }else {
-            eprintln!("WARNING: referenced tx for dispute does not exist in history!!!, {tx_id}" );
+        };
+
+        self.post(tx_id, LedgerAccount::ClientAvailable, LedgerAccount::ClientHeld, &Some(currency.clone()), held);
+        self.disputes.entry(tx_id).or_default().push(OpenDispute { raised_at: dispute.timestamp, currency, amount, held });
+        self.disputes_raised += 1;
+
+        // only an active account moves to collections, stricter states stay as they are
+        if matches!(outcome, Some(OverdraftOutcome::Collections { .. })) && self.state == AccountState::Active {
+            self.set_state(tx_id, AccountState::Collections, format!("dispute of tx {} overdrew the account", tx_id), dispute.timestamp);
+        }
+        Ok(outcome)
+    }
+
+    /// Part of a tx amount that is not under an open dispute, charged back or refunded, None when the tx is unknown or has no amount
+    pub fn undisputed_amount(&self, tx_id: u32) -> Option<Decimal> {
+        let amount = self.tx_history.get(&tx_id)?.amount?;
+        let open: Decimal = self.disputes.get(&tx_id).into_iter().flatten().map(|open| open.amount).sum();
+        let charged_back = self.charged_back.get(&tx_id).copied().unwrap_or_default();
+        let refunded = self.refunded.get(&tx_id).copied().unwrap_or_default();
+        Some(amount - open - charged_back - refunded)
+    }
+
+    /// The open dispute a resolve or chargeback of `tx_id` settles: the oldest one for exactly `amount`,
+    /// or the oldest one of all when no amount is given
+    pub fn open_dispute(&self, tx_id: u32, amount: Option<Decimal>) -> Option<&OpenDispute> {
+        self.disputes.get(&tx_id)?
+            .iter()
+            .find(|open| amount.is_none_or(|amount| open.amount == amount))
+    }
+
+    fn take_dispute(&mut self, tx_id: u32, settles: impl Fn(&OpenDispute) -> bool) -> Option<OpenDispute> {
+        let portions = self.disputes.get_mut(&tx_id)?;
+        let index = portions.iter().position(settles)?;
+        let open = portions.remove(index);
+        if portions.is_empty() {
+            self.disputes.remove(&tx_id);
+        }
+        Some(open)
+    }
+
+    /// Return a client in collections to active once no currency is overdrawn any more
+    fn leave_collections(&mut self, tx_id: u32, timestamp: Option<DateTime<Utc>>) {
+        if self.state == AccountState::Collections
+            && self.balances().values().all(|balance| balance.available >= Decimal::ZERO)
+        {
+            self.set_state(tx_id, AccountState::Active, "overdraft repaid".to_string(), timestamp);
+        }
+    }
+
+    /// Current open dispute count and the share of deposits under dispute
+    pub fn dispute_stats(&self) -> DisputeStats {
+        let mut disputed: BTreeMap<Currency, Decimal> = BTreeMap::new();
+        for open in self.disputes.values().flatten() {
+            *disputed.entry(open.currency.clone()).or_default() += open.amount;
         }
+
+        let disputed_ratio = disputed.iter()
+            .filter_map(|(currency, amount)| {
+                let deposited = self.deposited.get(currency).copied().unwrap_or_default();
+                (deposited > Decimal::ZERO).then(|| amount / deposited)
+            })
+            .max()
+            .unwrap_or_default();
+
+        DisputeStats { open_disputes: self.disputes.values().map(Vec::len).sum(), disputed_ratio }
+    }
+
+    /// Freeze an active account once its dispute activity reaches `thresholds`, called after `dispute`.
+    /// Returns the statistics that triggered the freeze.
+    pub fn freeze_on_dispute_abuse(&mut self, dispute: &CsvTransaction, thresholds: &AbuseThresholds) -> Option<DisputeStats> {
+        if self.state != AccountState::Active {
+            return None;
+        }
+
+        let stats = self.dispute_stats();
+        if !thresholds.crossed(&stats) {
+            return None;
+        }
+
+        let reason = format!(
+            "dispute abuse: {} open disputes, {} of deposits disputed",
+            stats.open_disputes, stats.disputed_ratio.round_dp(4)
+        );
+        self.set_state(dispute.tx_id, AccountState::Frozen, reason, dispute.timestamp);
+        Some(stats)
     }
 
     /// A resolve represents the resolution to a dispute, releasing held funds.
     /// Held decreases, available increases, total remains the same.
-    /// Ignore non-existing transactions or transactions not under dispute.
-    pub fn resolve(&mut self, tx_id: u32) {
-        if let Some(tx) = self.tx_history.get(&tx_id) {
-
-            // assuming when a transaction is found it is under dispute
-            match tx.amount {
-                Some(amount) => {
-                    self.held -= amount;
-                    self.available += amount;
-                }
-                None => {
-                    eprintln!("WARNING: referenced tx for resolve had no amount!!!, {}:{}", tx.client_id, tx.tx_id  );
-                }
-            }
-        }else {
-            eprintln!("WARNING: referenced tx for resolve does not exist in history!!!, {tx_id}" );
+    /// With an amount only the open dispute of that portion is resolved, see `open_dispute`.
+    /// Transactions that do not exist or are not under dispute are rejected.
+    /// The resolve's timestamp goes on the audit entry when it takes the client out of collections.
+    pub fn resolve(&mut self, resolve: &CsvTransaction) -> Result<(), ApplicationError> {
+        self.check_allowed(TransactionType::Resolve, resolve)?;
+        let open = self.take_dispute(resolve.tx_id, |open| resolve.amount.is_none_or(|amount| open.amount == amount))
+            .ok_or(NotUnderDispute(resolve.client_id, resolve.tx_id))?;
+        self.release(resolve.tx_id, open, resolve.timestamp);
+        Ok(())
+    }
+
+    fn release(&mut self, tx_id: u32, open: OpenDispute, timestamp: Option<DateTime<Utc>>) {
+        // release what the dispute actually held, the overdraft policy may have held less than the disputed amount
+        self.post(tx_id, LedgerAccount::ClientHeld, LedgerAccount::ClientAvailable, &Some(open.currency), open.held);
+        self.leave_collections(tx_id, timestamp);
+    }
+
+    /// Settle the open dispute of `tx_id` raised at `raised_at` as `action`, once it has been open too long.
+    /// Portions are told apart by when they were raised, another open portion of the same amount is left alone.
+    /// Returns false when no such dispute is open.
+    pub fn settle_expired(&mut self, tx_id: u32, raised_at: DateTime<Utc>, action: ExpiryAction, timestamp: Option<DateTime<Utc>>) -> bool {
+        let Some(open) = self.take_dispute(tx_id, |open| open.raised_at == Some(raised_at)) else {
+            return false;
+        };
+        match action {
+            ExpiryAction::Resolve => self.release(tx_id, open, timestamp),
+            ExpiryAction::Chargeback => self.charge_back(tx_id, open, timestamp),
         }
+        true
+    }
+
+    /// Admin operations (unlock, freeze, close, reopen) move the account through its lifecycle.
+    /// Each one must carry an audit reason and is only allowed from certain states.
+    pub fn change_state(&mut self, tx: &CsvTransaction) -> Result<(), ApplicationError> {
+        let reason = tx.reason.clone()
+            .filter(|reason| !reason.is_empty())
+            .ok_or(MissingAuditReason(tx.client_id, tx.tx_id))?;
+
+        let to = self.state.transition(&tx.tx_type)
+            .ok_or(TransactionNotAllowed(tx.client_id, tx.tx_id, self.state))?;
+
+        self.set_state(tx.tx_id, to, reason, tx.timestamp);
+        Ok(())
+    }
+
+    fn set_state(&mut self, tx_id: u32, to: AccountState, reason: String, timestamp: Option<DateTime<Utc>>) {
+        self.audit_log.push(AuditEntry { tx_id, from: self.state, to, reason, timestamp });
+        self.state = to;
     }
 
     /// A chargeback is the final state of a dispute, reversing the transaction.
-    /// Held and total decrease by the disputed amount, and the client account is locked.
-    /// Ignore non-existing transactions or transactions not under dispute.
-    pub fn chargeback(&mut self, tx_id: u32) {
-        if let Some(tx) = self.tx_history.get(&tx_id) {
-            match tx.amount {
-                Some(amount) => {
-                    self.held -= amount;
-                    self.total -= amount;
-                    self.locked = true;
-                }
-                None => {
-                    eprintln!("WARNING: referenced tx for chargeback had no amount!!!, {}:{}", tx.client_id, tx.tx_id ) ;
-                }
-            }
-        } else {
-           eprintln!("WARNING: referenced tx for chargeback does not exist in history!!!, {tx_id}" );
-        }
+    /// Held and total decrease by the held amount, and the client account is locked.
+    /// With an amount only the open dispute of that portion is charged back, see `open_dispute`.
+    /// Transactions that do not exist or are not under dispute are rejected.
+    /// The chargeback's timestamp goes on the audit entry of the lock.
+    pub fn chargeback(&mut self, chargeback: &CsvTransaction) -> Result<(), ApplicationError> {
+        self.check_allowed(TransactionType::Chargeback, chargeback)?;
+        let open = self.take_dispute(chargeback.tx_id, |open| chargeback.amount.is_none_or(|amount| open.amount == amount))
+            .ok_or(NotUnderDispute(chargeback.client_id, chargeback.tx_id))?;
+        self.charge_back(chargeback.tx_id, open, chargeback.timestamp);
+        Ok(())
+    }
+
+    fn charge_back(&mut self, tx_id: u32, open: OpenDispute, timestamp: Option<DateTime<Utc>>) {
+        self.post(tx_id, LedgerAccount::ClientHeld, LedgerAccount::ChargebackLoss, &Some(open.currency), open.held);
+        *self.charged_back.entry(tx_id).or_default() += open.amount;
+        self.set_state(tx_id, AccountState::Locked, format!("chargeback of tx {}", tx_id), timestamp);
     }
 }
 
@@ -121,42 +448,41 @@ impl Client {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::tx;
     use rust_decimal::Decimal;
     use rust_decimal::prelude::FromPrimitive;
-    use crate::csv_ingestor::TransactionType;
-    use crate::error::ApplicationError;
+    use chrono::TimeDelta;
 
-    // helper to create CsvTransaction
-    fn make_tx(client_id: u16, tx_id: u32, amount: Option<f64>, tx_type: TransactionType) -> CsvTransaction {
-        CsvTransaction {
-            client_id,
-            tx_id,
-            tx_type,
-            amount: amount.map(|a| Decimal::from_f64(a).unwrap()),
-        }
+    fn balance(client: &Client) -> Balance {
+        client.balance(&Currency::default())
+    }
+
+    fn at(t: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(t).unwrap().with_timezone(&Utc)
     }
 
+
     #[test]
     fn test_deposit_and_withdraw() {
         let mut client = Client::default();
 
-        let dep = make_tx(1, 1, Some(100.0), TransactionType::Deposit);
-        client.deposit(&dep);
+        let dep = tx(TransactionType::Deposit, 1, 1).with_amount(100);
+        client.deposit(&dep).unwrap();
 
-        assert_eq!(client.available, Decimal::from_f64(100.0).unwrap());
-        assert_eq!(client.held, Decimal::zero());
-        assert_eq!(client.total, Decimal::from_f64(100.0).unwrap());
+        assert_eq!(balance(&client).available, Decimal::from_f64(100.0).unwrap());
+        assert_eq!(balance(&client).held, Decimal::zero());
+        assert_eq!(balance(&client).total, Decimal::from_f64(100.0).unwrap());
 
-        let withdrawal = make_tx(1, 2, Some(40.0), TransactionType::Withdrawal);
-        client.withdraw(&withdrawal).unwrap();
+        let withdrawal = tx(TransactionType::Withdrawal, 1, 2).with_amount(40);
+        client.withdraw(&withdrawal, &WithdrawalLimits::default()).unwrap();
 
-        assert_eq!(client.available, Decimal::from_f64(60.0).unwrap());
-        assert_eq!(client.held, Decimal::zero());
-        assert_eq!(client.total, Decimal::from_f64(60.0).unwrap());
+        assert_eq!(balance(&client).available, Decimal::from_f64(60.0).unwrap());
+        assert_eq!(balance(&client).held, Decimal::zero());
+        assert_eq!(balance(&client).total, Decimal::from_f64(60.0).unwrap());
 
         // Withdraw more than available should error
-        let bad_withdrawal = make_tx(1, 3, Some(100.0), TransactionType::Withdrawal);
-        let err = client.withdraw(&bad_withdrawal).unwrap_err();
+        let bad_withdrawal = tx(TransactionType::Withdrawal, 1, 3).with_amount(100);
+        let err = client.withdraw(&bad_withdrawal, &WithdrawalLimits::default()).unwrap_err();
         match err {
             InsufficientAvailableBalanceForWithdrawal(client_id, tx_id) => {
                 assert_eq!(client_id, 1);
@@ -170,56 +496,507 @@ mod tests {
     fn test_dispute_resolve_chargeback() {
         let mut client = Client::default();
 
-        let dep = make_tx(1, 1, Some(100.0), TransactionType::Deposit);
-        client.deposit(&dep);
+        let dep = tx(TransactionType::Deposit, 1, 1).with_amount(100);
+        client.deposit(&dep).unwrap();
 
         // dispute
-        client.dispute(1);
-        assert_eq!(client.available, Decimal::from_f64(0.0).unwrap());
-        assert_eq!(client.held, Decimal::from_f64(100.0).unwrap());
-        assert_eq!(client.total, Decimal::from_f64(100.0).unwrap());
-        assert!(!client.locked);
+        let dispute = tx(TransactionType::Dispute, 1, 1);
+        client.dispute(&dispute, &DisputePolicy::default()).unwrap();
+        assert_eq!(balance(&client).available, Decimal::from_f64(0.0).unwrap());
+        assert_eq!(balance(&client).held, Decimal::from_f64(100.0).unwrap());
+        assert_eq!(balance(&client).total, Decimal::from_f64(100.0).unwrap());
+        assert_eq!(client.state, AccountState::Active);
 
         // resolve
-        client.resolve(1);
-        assert_eq!(client.available, Decimal::from_f64(100.0).unwrap());
-        assert_eq!(client.held, Decimal::from_f64(0.0).unwrap());
-        assert_eq!(client.total, Decimal::from_f64(100.0).unwrap());
-        assert!(!client.locked);
+        client.resolve(&tx(TransactionType::Resolve, 1, 1)).unwrap();
+        assert_eq!(balance(&client).available, Decimal::from_f64(100.0).unwrap());
+        assert_eq!(balance(&client).held, Decimal::from_f64(0.0).unwrap());
+        assert_eq!(balance(&client).total, Decimal::from_f64(100.0).unwrap());
+        assert_eq!(client.state, AccountState::Active);
 
         // dispute again
-        client.dispute(1);
+        client.dispute(&dispute, &DisputePolicy::default()).unwrap();
         // chargeback
-        client.chargeback(1);
-        assert_eq!(client.available, Decimal::from_f64(0.0).unwrap());
-        assert_eq!(client.held, Decimal::from_f64(0.0).unwrap());
-        assert_eq!(client.total, Decimal::from_f64(0.0).unwrap());
-        assert!(client.locked);
+        client.chargeback(&tx(TransactionType::Chargeback, 1, 1)).unwrap();
+        assert_eq!(balance(&client).available, Decimal::from_f64(0.0).unwrap());
+        assert_eq!(balance(&client).held, Decimal::from_f64(0.0).unwrap());
+        assert_eq!(balance(&client).total, Decimal::from_f64(0.0).unwrap());
+        assert_eq!(client.state, AccountState::Locked);
     }
 
     #[test]
     fn test_dispute_nonexistent_tx() {
         let mut client = Client::default();
 
-        // disputing a non-existent transaction should do nothing
-        client.dispute(999);
-        client.resolve(999);
-        client.chargeback(999);
-
-        assert_eq!(client.available, Decimal::from_f64(0.0).unwrap());
-        assert_eq!(client.held, Decimal::from_f64(0.0).unwrap());
-        assert_eq!(client.total, Decimal::from_f64(0.0).unwrap());
-        assert!(!client.locked);
+        // disputing a non-existent transaction is rejected and does nothing
+        assert!(matches!(
+            client.dispute(&tx(TransactionType::Dispute, 1, 999), &DisputePolicy::default()),
+            Err(DisputeOfUnknownTransaction(1, 999))
+        ));
+        assert!(matches!(client.resolve(&tx(TransactionType::Resolve, 1, 999)), Err(NotUnderDispute(1, 999))));
+        assert!(matches!(client.chargeback(&tx(TransactionType::Chargeback, 1, 999)), Err(NotUnderDispute(1, 999))));
+
+        assert_eq!(balance(&client).available, Decimal::from_f64(0.0).unwrap());
+        assert_eq!(balance(&client).held, Decimal::from_f64(0.0).unwrap());
+        assert_eq!(balance(&client).total, Decimal::from_f64(0.0).unwrap());
+        assert_eq!(client.state, AccountState::Active);
     }
 
     #[test]
     fn test_deposit_without_amount() {
         let mut client = Client::default();
-        let tx = make_tx(1, 10, None, TransactionType::Deposit);
-        client.deposit(&tx);
+        let tx = tx(TransactionType::Deposit, 1, 10);
+        client.deposit(&tx).unwrap();
 
         // no change since amount is None
-        assert_eq!(client.available, Decimal::from_f64(0.0).unwrap());
-        assert_eq!(client.total, Decimal::from_f64(0.0).unwrap());
+        assert_eq!(balance(&client).available, Decimal::from_f64(0.0).unwrap());
+        assert_eq!(balance(&client).total, Decimal::from_f64(0.0).unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_resolve_and_chargeback_require_open_dispute() {
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(100)).unwrap();
+
+        // nothing is held until the tx is disputed
+        assert!(matches!(client.resolve(&tx(TransactionType::Resolve, 1, 1)), Err(NotUnderDispute(1, 1))));
+        assert!(matches!(client.chargeback(&tx(TransactionType::Chargeback, 1, 1)), Err(NotUnderDispute(1, 1))));
+        assert_eq!(balance(&client).available, Decimal::from_f64(100.0).unwrap());
+        assert_eq!(balance(&client).held, Decimal::zero());
+        assert_eq!(client.state, AccountState::Active);
+
+        // a second dispute of the same tx is rejected, it holds nothing more
+        let dispute = tx(TransactionType::Dispute, 1, 1);
+        client.dispute(&dispute, &DisputePolicy::default()).unwrap();
+        assert!(matches!(client.dispute(&dispute, &DisputePolicy::default()), Err(InvalidDisputeAmount(1, 1))));
+        assert_eq!(balance(&client).available, Decimal::zero());
+        assert_eq!(balance(&client).held, Decimal::from_f64(100.0).unwrap());
+    }
+
+    #[test]
+    fn test_dispute_window() {
+        let mut client = Client::default();
+        let policy = DisputePolicy { window: Some(TimeDelta::days(120)), ..DisputePolicy::default() };
+
+        let dep = tx(TransactionType::Deposit, 1, 1).with_amount(100).at("2026-01-01T00:00:00Z");
+        client.deposit(&dep).unwrap();
+
+        let late = tx(TransactionType::Dispute, 1, 1).at("2026-06-01T00:00:00Z");
+        match client.dispute(&late, &policy).unwrap_err() {
+            DisputeWindowExpired(client_id, tx_id) => {
+                assert_eq!(client_id, 1);
+                assert_eq!(tx_id, 1);
+            }
+            _ => panic!("Expected DisputeWindowExpired error"),
+        }
+        assert_eq!(balance(&client).held, Decimal::zero());
+
+        let in_time = tx(TransactionType::Dispute, 1, 1).at("2026-03-01T00:00:00Z");
+        client.dispute(&in_time, &policy).unwrap();
+        assert_eq!(balance(&client).held, Decimal::from_f64(100.0).unwrap());
+        assert_eq!(client.open_dispute(1, None).and_then(|open| open.raised_at), in_time.timestamp);
+    }
+
+    #[test]
+    fn test_admin_operations() {
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(100)).unwrap();
+        client.dispute(&tx(TransactionType::Dispute, 1, 1), &DisputePolicy::default()).unwrap();
+        client.chargeback(&tx(TransactionType::Chargeback, 1, 1)).unwrap();
+        assert_eq!(client.state, AccountState::Locked);
+
+        // admin operations need a reason
+        let unlock = tx(TransactionType::Unlock, 1, 2);
+        assert!(matches!(client.change_state(&unlock), Err(MissingAuditReason(1, 2))));
+
+        client.change_state(&unlock.with_reason("chargeback reversed by bank")).unwrap();
+        assert_eq!(client.state, AccountState::Active);
+
+        // reopen is only valid for closed accounts
+        let reopen = tx(TransactionType::Reopen, 1, 3).with_reason("customer request");
+        match client.change_state(&reopen).unwrap_err() {
+            TransactionNotAllowed(client_id, tx_id, state) => {
+                assert_eq!(client_id, 1);
+                assert_eq!(tx_id, 3);
+                assert_eq!(state, AccountState::Active);
+            }
+            _ => panic!("Expected TransactionNotAllowed error"),
+        }
+
+        let audit: Vec<(AccountState, AccountState)> = client.audit_log.iter().map(|e| (e.from, e.to)).collect();
+        assert_eq!(audit, vec![
+            (AccountState::Active, AccountState::Locked),
+            (AccountState::Locked, AccountState::Active),
+        ]);
+        assert_eq!(client.audit_log[1].reason, "chargeback reversed by bank");
+    }
+
+    #[test]
+    fn test_balances_per_currency() {
+        let mut client = Client::default();
+        let eur: Currency = "EUR".parse().unwrap();
+        let gbp: Currency = "GBP".parse().unwrap();
+
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(100).with_currency("EUR")).unwrap();
+        client.deposit(&tx(TransactionType::Deposit, 1, 2).with_amount(20).with_currency("GBP")).unwrap();
+
+        // withdrawals only draw on their own currency
+        let withdrawal = tx(TransactionType::Withdrawal, 1, 3).with_amount(50).with_currency("GBP");
+        assert!(client.withdraw(&withdrawal, &WithdrawalLimits::default()).is_err());
+
+        // the dispute row carries no currency, it acts on the original deposit's
+        client.dispute(&tx(TransactionType::Dispute, 1, 1), &DisputePolicy::default()).unwrap();
+        assert_eq!(client.balance(&eur), Balance {
+            available: Decimal::zero(),
+            held: Decimal::from_f64(100.0).unwrap(),
+            total: Decimal::from_f64(100.0).unwrap(),
+        });
+        assert_eq!(client.balance(&gbp).available, Decimal::from_f64(20.0).unwrap());
+        assert_eq!(client.balance(&gbp).held, Decimal::zero());
+    }
+
+    #[test]
+    fn test_withdrawal_limits() {
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(1000)).unwrap();
+
+        let limits = WithdrawalLimits {
+            max_single: Some(Decimal::from(300)),
+            daily_total: Some(Decimal::from(500)),
+            velocity: Some((2, TimeDelta::hours(1))),
+        };
+        let withdraw = |client: &mut Client, tx_id: u32, amount: i64, timestamp: &str| {
+            client.withdraw(&tx(TransactionType::Withdrawal, 1, tx_id).with_amount(amount).at(timestamp), &limits)
+        };
+        let rule = |result: Result<(), ApplicationError>| match result {
+            Err(LimitExceeded(_, _, rule)) => rule,
+            other => panic!("Expected LimitExceeded error, got {:?}", other),
+        };
+
+        assert_eq!(rule(withdraw(&mut client, 2, 301, "2026-03-01T08:00:00Z")), MAX_SINGLE_WITHDRAWAL);
+
+        withdraw(&mut client, 3, 100, "2026-03-01T08:00:00Z").unwrap();
+        withdraw(&mut client, 4, 100, "2026-03-01T08:10:00Z").unwrap();
+        assert_eq!(rule(withdraw(&mut client, 5, 100, "2026-03-01T08:20:00Z")), WITHDRAWAL_VELOCITY);
+
+        withdraw(&mut client, 6, 300, "2026-03-01T10:00:00Z").unwrap();
+        assert_eq!(rule(withdraw(&mut client, 7, 1, "2026-03-01T12:00:00Z")), DAILY_WITHDRAWAL_TOTAL);
+
+        // a new day starts from zero
+        withdraw(&mut client, 8, 300, "2026-03-02T00:00:00Z").unwrap();
+        assert_eq!(balance(&client).available, Decimal::from(200));
+    }
+
+    #[test]
+    fn test_freeze_on_dispute_abuse() {
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(60)).unwrap();
+        client.deposit(&tx(TransactionType::Deposit, 1, 2).with_amount(40)).unwrap();
+
+        let thresholds = AbuseThresholds {
+            max_open_disputes: Some(2),
+            max_disputed_ratio: Some(Decimal::from_f64(0.5).unwrap()),
+        };
+
+        // 40 of 100 disputed, one open dispute
+        let first = tx(TransactionType::Dispute, 1, 2);
+        client.dispute(&first, &DisputePolicy::default()).unwrap();
+        assert_eq!(client.freeze_on_dispute_abuse(&first, &thresholds), None);
+        assert_eq!(client.state, AccountState::Active);
+
+        // everything disputed
+        let second = tx(TransactionType::Dispute, 1, 1);
+        client.dispute(&second, &DisputePolicy::default()).unwrap();
+        let stats = client.freeze_on_dispute_abuse(&second, &thresholds).expect("client should be frozen");
+        assert_eq!(stats, DisputeStats { open_disputes: 2, disputed_ratio: Decimal::ONE });
+        assert_eq!(client.state, AccountState::Frozen);
+        assert_eq!(client.audit_log.last().unwrap().tx_id, 1);
+    }
+
+    #[test]
+    fn test_ledger_postings() {
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(100)).unwrap();
+        client.withdraw(&tx(TransactionType::Withdrawal, 1, 2).with_amount(30), &WithdrawalLimits::default()).unwrap();
+        client.dispute(&tx(TransactionType::Dispute, 1, 1), &DisputePolicy::default()).unwrap();
+        client.chargeback(&tx(TransactionType::Chargeback, 1, 1)).unwrap();
+
+        let moves: Vec<(u32, LedgerAccount, LedgerAccount)> = client.ledger.postings().iter()
+            .map(|p| (p.tx_id, p.from, p.to))
+            .collect();
+        assert_eq!(moves, vec![
+            (1, LedgerAccount::Settlement, LedgerAccount::ClientAvailable),
+            (2, LedgerAccount::ClientAvailable, LedgerAccount::Settlement),
+            (1, LedgerAccount::ClientAvailable, LedgerAccount::ClientHeld),
+            (1, LedgerAccount::ClientHeld, LedgerAccount::ChargebackLoss),
+        ]);
+
+        // the disputed funds were partly withdrawn already
+        assert_eq!(balance(&client), Balance {
+            available: Decimal::from_f64(-30.0).unwrap(),
+            held: Decimal::zero(),
+            total: Decimal::from_f64(-30.0).unwrap(),
+        });
+        assert_eq!(client.ledger.balance(LedgerAccount::ChargebackLoss, &Currency::default()), Decimal::from_f64(100.0).unwrap());
+    }
+
+    #[test]
+    fn test_overdraft_policies() {
+        // 100 deposited, 70 withdrawn, then the deposit is disputed
+        let overdrawn = |overdraft: OverdraftPolicy| {
+            let mut client = Client::default();
+            client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(100)).unwrap();
+            client.withdraw(&tx(TransactionType::Withdrawal, 1, 2).with_amount(70), &WithdrawalLimits::default()).unwrap();
+            let policy = DisputePolicy { overdraft, ..DisputePolicy::default() };
+            let outcome = client.dispute(&tx(TransactionType::Dispute, 1, 1), &policy);
+            (client, outcome)
+        };
+
+        let (client, outcome) = overdrawn(OverdraftPolicy::AllowNegative);
+        assert_eq!(outcome.unwrap(), Some(OverdraftOutcome::AllowedNegative { requested: Decimal::from(100), available: Decimal::from(30) }));
+        assert_eq!(balance(&client).available, Decimal::from(-70));
+
+        let (client, outcome) = overdrawn(OverdraftPolicy::Reject);
+        assert!(matches!(outcome, Err(InsufficientAvailableBalanceForDispute(1, 1))));
+        assert_eq!(balance(&client).held, Decimal::zero());
+        assert!(client.disputes.is_empty());
+
+        let (mut client, outcome) = overdrawn(OverdraftPolicy::HoldAvailable);
+        assert_eq!(outcome.unwrap(), Some(OverdraftOutcome::HeldAvailable { requested: Decimal::from(100), held: Decimal::from(30) }));
+        assert_eq!(balance(&client), Balance { available: Decimal::zero(), held: Decimal::from(30), total: Decimal::from(30) });
+        // the chargeback only takes what was held
+        client.chargeback(&tx(TransactionType::Chargeback, 1, 1)).unwrap();
+        assert_eq!(balance(&client).total, Decimal::zero());
+
+        let (mut client, outcome) = overdrawn(OverdraftPolicy::Collections { overdraft_limit: Decimal::from(50) });
+        assert_eq!(outcome.unwrap(), Some(OverdraftOutcome::Collections { requested: Decimal::from(100), held: Decimal::from(80) }));
+        assert_eq!(balance(&client).available, Decimal::from(-50));
+        assert_eq!(client.state, AccountState::Collections);
+        assert!(!client.state.allows(&TransactionType::Withdrawal));
+
+        // repaying the overdraft returns the client to active
+        client.deposit(&tx(TransactionType::Deposit, 1, 3).with_amount(20)).unwrap();
+        assert_eq!(client.state, AccountState::Collections);
+        client.deposit(&tx(TransactionType::Deposit, 1, 4).with_amount(30)).unwrap();
+        assert_eq!(client.state, AccountState::Active);
+
+        // so does resolving the dispute that overdrew it, as of the resolve
+        let (mut client, _) = overdrawn(OverdraftPolicy::Collections { overdraft_limit: Decimal::from(50) });
+        client.resolve(&tx(TransactionType::Resolve, 1, 1).at("2026-03-05T10:00:00Z")).unwrap();
+        let entry = client.audit_log.last().unwrap();
+        assert_eq!((entry.from, entry.to, entry.timestamp), (AccountState::Collections, AccountState::Active, Some(at("2026-03-05T10:00:00Z"))));
+    }
+
+    #[test]
+    fn test_partial_disputes() {
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(100)).unwrap();
+        let policy = DisputePolicy::default();
+
+        client.dispute(&tx(TransactionType::Dispute, 1, 1).with_amount(30), &policy).unwrap();
+        client.dispute(&tx(TransactionType::Dispute, 1, 1).with_amount(50), &policy).unwrap();
+        assert_eq!(balance(&client).held, Decimal::from(80));
+        assert_eq!(client.undisputed_amount(1), Some(Decimal::from(20)));
+
+        // never more than the original amount
+        assert!(matches!(
+            client.dispute(&tx(TransactionType::Dispute, 1, 1).with_amount(30), &policy),
+            Err(InvalidDisputeAmount(1, 1))
+        ));
+
+        // settle the 50 portion, the 30 one stays open
+        client.resolve(&tx(TransactionType::Resolve, 1, 1).with_amount(50)).unwrap();
+        assert_eq!(balance(&client).held, Decimal::from(30));
+        assert_eq!(client.dispute_stats().open_disputes, 1);
+
+        // a dispute without amount takes whatever is left undisputed
+        client.dispute(&tx(TransactionType::Dispute, 1, 1), &policy).unwrap();
+        assert_eq!(balance(&client).held, Decimal::from(100));
+
+        client.chargeback(&tx(TransactionType::Chargeback, 1, 1).with_amount(30)).unwrap();
+        assert_eq!(balance(&client), Balance { available: Decimal::zero(), held: Decimal::from(70), total: Decimal::from(70) });
+        assert_eq!(client.undisputed_amount(1), Some(Decimal::zero()));
+        // the chargeback locked the account, unlock it to settle the rest
+        let unlock = tx(TransactionType::Unlock, 1, 2).with_reason("reviewed");
+        client.change_state(&unlock).unwrap();
+
+        // an amount matching no open portion settles nothing
+        assert!(matches!(client.resolve(&tx(TransactionType::Resolve, 1, 1).with_amount(5)), Err(NotUnderDispute(1, 1))));
+        assert_eq!(balance(&client).held, Decimal::from(70));
+        client.resolve(&tx(TransactionType::Resolve, 1, 1)).unwrap();
+        assert_eq!(balance(&client).available, Decimal::from(70));
+        assert!(client.disputes.is_empty());
+        assert_eq!(client.undisputed_amount(1), Some(Decimal::from(70)));
+    }
+
+    #[test]
+    fn test_refunds() {
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(100)).unwrap();
+        client.deposit(&tx(TransactionType::Deposit, 1, 2).with_amount(50)).unwrap();
+        client.withdraw(&tx(TransactionType::Withdrawal, 1, 3).with_amount(10), &WithdrawalLimits::default()).unwrap();
+
+        client.refund(&tx(TransactionType::Refund, 1, 1).with_amount(60)).unwrap();
+        assert_eq!(balance(&client), Balance { available: Decimal::from(80), held: Decimal::zero(), total: Decimal::from(80) });
+
+        // cumulative refunds stay within the deposit
+        assert!(matches!(client.refund(&tx(TransactionType::Refund, 1, 1).with_amount(50)), Err(RefundExceedsDeposit(1, 1))));
+        // refunded funds cannot be disputed any more
+        assert_eq!(client.undisputed_amount(1), Some(Decimal::from(40)));
+
+        // disputed deposits cannot be refunded
+        client.dispute(&tx(TransactionType::Dispute, 1, 2), &DisputePolicy::default()).unwrap();
+        assert!(matches!(client.refund(&tx(TransactionType::Refund, 1, 2)), Err(RefundOfDisputedTransaction(1, 2))));
+        client.resolve(&tx(TransactionType::Resolve, 1, 2)).unwrap();
+
+        // without an amount the rest of the deposit is refunded
+        client.refund(&tx(TransactionType::Refund, 1, 1)).unwrap();
+        // withdrawals and unknown transactions cannot be refunded
+        assert!(matches!(client.refund(&tx(TransactionType::Refund, 1, 3).with_amount(5)), Err(RefundOfUnknownDeposit(1, 3))));
+        assert!(matches!(client.refund(&tx(TransactionType::Refund, 1, 9).with_amount(5)), Err(RefundOfUnknownDeposit(1, 9))));
+        assert_eq!(balance(&client).available, Decimal::from(40));
+        assert_eq!(client.refunded.get(&1), Some(&Decimal::from(100)));
+        assert!(matches!(client.refund(&tx(TransactionType::Refund, 1, 1)), Err(RefundExceedsDeposit(1, 1))));
+
+        // amounts must be positive
+        assert!(matches!(client.refund(&tx(TransactionType::Refund, 1, 2).with_amount(0)), Err(InvalidRefundAmount(1, 2))));
+        assert!(matches!(client.refund(&tx(TransactionType::Refund, 1, 2).with_amount(-5)), Err(InvalidRefundAmount(1, 2))));
+        // a deposit recorded without an amount is not one to refund
+        client.tx_history.insert(4, tx(TransactionType::Deposit, 1, 4));
+        assert!(matches!(client.refund(&tx(TransactionType::Refund, 1, 4).with_amount(5)), Err(RefundOfUnknownDeposit(1, 4))));
+        assert_eq!(balance(&client).available, Decimal::from(40));
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+        use crate::lifecycle::AccountState;
+
+        #[derive(Debug, Clone)]
+        enum Op {
+            Deposit(Decimal),
+            Withdraw(Decimal),
+            // the index picks one of the deposits made so far
+            Dispute(usize),
+            Resolve(usize),
+            Chargeback(usize),
+        }
+
+        fn amount() -> impl Strategy<Value = Decimal> {
+            (1i64..1_000_000).prop_map(|units| Decimal::new(units, 4))
+        }
+
+        fn op() -> impl Strategy<Value = Op> {
+            prop_oneof![
+                3 => amount().prop_map(Op::Deposit),
+                2 => amount().prop_map(Op::Withdraw),
+                2 => any::<usize>().prop_map(Op::Dispute),
+                2 => any::<usize>().prop_map(Op::Resolve),
+                1 => any::<usize>().prop_map(Op::Chargeback),
+            ]
+        }
+
+        /// Where a deposit stands in the model
+        #[derive(Debug, Clone, Copy)]
+        enum Deposit {
+            Settled,
+            Disputed,
+            ChargedBack,
+        }
+
+        /// What the client should hold, worked out from the operations alone: applied deposits minus applied
+        /// withdrawals minus charged back deposits, with disputed deposits held
+        #[derive(Debug, Default)]
+        struct Model {
+            available: Decimal,
+            held: Decimal,
+            locked: bool,
+            deposits: Vec<(u32, Decimal, Deposit)>,
+        }
+
+        impl Model {
+            /// Tx id the `index` of a dispute, resolve or chargeback refers to, 0 (unknown) before any deposit
+            fn pick(&self, index: usize) -> u32 {
+                self.deposits.get(index % self.deposits.len().max(1)).map_or(0, |(tx_id, _, _)| *tx_id)
+            }
+
+            /// Whether the client should accept `op`, updating the model when it does
+            fn apply(&mut self, tx_id: u32, op: &Op) -> bool {
+                if self.locked {
+                    return false;
+                }
+
+                match *op {
+                    Op::Deposit(amount) => {
+                        self.available += amount;
+                        self.deposits.push((tx_id, amount, Deposit::Settled));
+                    }
+                    Op::Withdraw(amount) if amount <= self.available => self.available -= amount,
+                    Op::Withdraw(_) => return false,
+                    Op::Dispute(index) | Op::Resolve(index) | Op::Chargeback(index) => {
+                        let len = self.deposits.len().max(1);
+                        let Some((_, amount, deposit)) = self.deposits.get_mut(index % len) else { return false };
+                        let amount = *amount;
+                        match (op, *deposit) {
+                            (Op::Dispute(_), Deposit::Settled) => {
+                                *deposit = Deposit::Disputed;
+                                self.available -= amount;
+                                self.held += amount;
+                            }
+                            (Op::Resolve(_), Deposit::Disputed) => {
+                                *deposit = Deposit::Settled;
+                                self.held -= amount;
+                                self.available += amount;
+                            }
+                            (Op::Chargeback(_), Deposit::Disputed) => {
+                                *deposit = Deposit::ChargedBack;
+                                self.held -= amount;
+                                self.locked = true;
+                            }
+                            _ => return false,
+                        }
+                    }
+                }
+                true
+            }
+        }
+
+        /// Call the client operation for `op` directly, whatever the account state, and tell whether it was accepted
+        fn apply(client: &mut Client, model: &Model, tx_id: u32, op: &Op) -> bool {
+            let tx = match *op {
+                Op::Deposit(amount) => tx(TransactionType::Deposit, 1, tx_id).with_amount(amount),
+                Op::Withdraw(amount) => tx(TransactionType::Withdrawal, 1, tx_id).with_amount(amount),
+                Op::Dispute(index) => tx(TransactionType::Dispute, 1, model.pick(index)),
+                Op::Resolve(index) => tx(TransactionType::Resolve, 1, model.pick(index)),
+                Op::Chargeback(index) => tx(TransactionType::Chargeback, 1, model.pick(index)),
+            };
+
+            match tx.tx_type {
+                TransactionType::Deposit => client.deposit(&tx).is_ok(),
+                TransactionType::Withdrawal => client.withdraw(&tx, &WithdrawalLimits::default()).is_ok(),
+                TransactionType::Dispute => client.dispute(&tx, &DisputePolicy::default()).is_ok(),
+                TransactionType::Resolve => client.resolve(&tx).is_ok(),
+                _ => client.chargeback(&tx).is_ok(),
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn client_matches_model_after_every_operation(ops in prop::collection::vec(op(), 1..60)) {
+                let mut client = Client::default();
+                let mut model = Model::default();
+
+                for (tx_id, op) in (1..).zip(&ops) {
+                    let accepted = apply(&mut client, &model, tx_id, op);
+                    prop_assert_eq!(accepted, model.apply(tx_id, op), "{:?}", op);
+
+                    let expected = Balance { available: model.available, held: model.held, total: model.available + model.held };
+                    prop_assert_eq!(balance(&client), expected);
+                    prop_assert_eq!(client.state == AccountState::Locked, model.locked);
+                    let disputed = model.deposits.iter().filter(|(_, _, deposit)| matches!(deposit, Deposit::Disputed)).count();
+                    prop_assert_eq!(client.dispute_stats().open_disputes, disputed);
+                }
+            }
+        }
+    }
+}
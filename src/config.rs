@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use chrono::TimeDelta;
+use crate::currency::Currency;
+use crate::dispute::DisputePolicy;
+use crate::error::ApplicationError;
+use crate::limits::LimitProfiles;
+use crate::risk::RiskRule;
+use crate::sequencer::OrderingPolicy;
+
+/// Runtime options shared by all workers
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub ordering: OrderingPolicy,
+    pub disputes: DisputePolicy,
+    /// Currency for rows that do not name one
+    pub default_currency: Currency,
+    pub limits: LimitProfiles,
+    /// Evaluated in order before each non-admin transaction
+    pub risk_rules: Vec<Arc<dyn RiskRule>>,
+    /// Check client invariants after every operation and alert on violations
+    pub audit: bool,
+}
+
+/// Parse a duration written as a whole number followed by a unit: `d`, `h`, `m` or `s`, e.g. `120d`
+pub fn parse_duration(s: &str) -> Result<TimeDelta, ApplicationError> {
+    let invalid = || ApplicationError::InvalidConfiguration(
+        format!("invalid duration '{}', expected e.g. 120d, 12h, 30m or 45s", s)
+    );
+
+    let split = s.len().checked_sub(1).filter(|i| s.is_char_boundary(*i)).ok_or_else(invalid)?;
+    let (value, unit) = s.split_at(split);
+    let value: i64 = value.parse().map_err(|_| invalid())?;
+
+    let duration = match unit {
+        "d" => TimeDelta::try_days(value),
+        "h" => TimeDelta::try_hours(value),
+        "m" => TimeDelta::try_minutes(value),
+        "s" => TimeDelta::try_seconds(value),
+        _ => None,
+    };
+    duration.filter(|d| *d >= TimeDelta::zero()).ok_or_else(invalid)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("120d").unwrap(), TimeDelta::days(120));
+        assert_eq!(parse_duration("12h").unwrap(), TimeDelta::hours(12));
+        assert_eq!(parse_duration("45s").unwrap(), TimeDelta::seconds(45));
+
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("-1d").is_err());
+        assert!(parse_duration("10w").is_err());
+    }
+}
@@ -1,15 +1,20 @@
 ///
 /// Module which ingests CSV data
 ///
+use std::fmt;
 use std::fs::File;
-use std::sync::mpsc::Sender;
-use csv::ReaderBuilder;
+use std::io::{self, Read};
+use std::sync::mpsc::SyncSender;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use csv::{ByteRecord, Position, ReaderBuilder, StringRecord};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Deserializer};
+use crate::currency::{Conversion, Currency, ExchangeRates};
 use crate::error::ApplicationError;
 
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub enum TransactionType {
     #[serde(rename = "deposit")]
     Deposit,
@@ -21,9 +26,45 @@ pub enum TransactionType {
     Resolve,
     #[serde(rename = "chargeback")]
     Chargeback,
+    /// Returns part or all of the deposit referenced by the tx id
+    #[serde(rename = "refund")]
+    Refund,
+    #[serde(rename = "unlock")]
+    Unlock,
+    #[serde(rename = "freeze")]
+    Freeze,
+    #[serde(rename = "close")]
+    Close,
+    #[serde(rename = "reopen")]
+    Reopen,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Refund => "refund",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Freeze => "freeze",
+            TransactionType::Close => "close",
+            TransactionType::Reopen => "reopen",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl TransactionType {
+    /// Admin operations change the account lifecycle instead of moving funds
+    pub fn is_admin(&self) -> bool {
+        matches!(self, TransactionType::Unlock | TransactionType::Freeze | TransactionType::Close | TransactionType::Reopen)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct CsvTransaction {
     #[serde(rename = "type")]
     pub tx_type: TransactionType,
@@ -33,6 +74,23 @@ pub struct CsvTransaction {
     pub tx_id: u32,
     #[serde(deserialize_with = "deserialize_decimal_opt")]
     pub amount: Option<Decimal>,
+    /// Optional RFC3339 event time, the column may be absent from the file altogether
+    #[serde(default, deserialize_with = "deserialize_timestamp_opt")]
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Optional currency code, rows without one are booked in the default currency
+    #[serde(default, deserialize_with = "deserialize_currency_opt")]
+    pub currency: Option<Currency>,
+    /// Audit reason, required by admin operations
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Set when the row was converted from a foreign currency at ingestion
+    #[serde(skip)]
+    pub conversion: Option<Conversion>,
+}
+
+/// Parse an amount as written in the input, rounded to the 4th decimal place
+pub fn parse_amount(s: &str) -> Result<Decimal, rust_decimal::Error> {
+    Ok(Decimal::from_str_exact(s)?.round_dp(4)) // round here
 }
 
 // Use custom deserializer and actually do the rounding to 4th decimal place
@@ -43,54 +101,376 @@ where
     let s: Option<String> = Option::deserialize(deserializer)?;
     match s {
         Some(s) => {
-            let d = Decimal::from_str_exact(&s)
-                .map_err(serde::de::Error::custom)?
-                .round_dp(4); // round here
+            let d = parse_amount(&s).map_err(serde::de::Error::custom)?;
             Ok(Some(d))
         }
         None => Ok(None),
     }
 }
 
-/// Read CSV in a streaming fashion and return deserialized batch
-pub fn read_csv(csv_path: &str, dispatcher_sender: Sender<CsvTransaction>) -> Result<Vec<CsvTransaction>, ApplicationError> {
+// Parse RFC3339 timestamps and normalise them to UTC
+fn deserialize_timestamp_opt<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    match s {
+        Some(s) => {
+            let timestamp = DateTime::parse_from_rfc3339(&s)
+                .map_err(serde::de::Error::custom)?
+                .with_timezone(&Utc);
+            Ok(Some(timestamp))
+        }
+        None => Ok(None),
+    }
+}
+
+fn deserialize_currency_opt<'de, D>(deserializer: D) -> Result<Option<Currency>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Option::deserialize(deserializer)?;
+    s.map(|s| s.parse().map_err(serde::de::Error::custom)).transpose()
+}
+
+/// CSV reader set up the way every input file is read: headers, fields trimmed
+pub fn csv_reader<R: Read>(reader: R) -> csv::Reader<R> {
+    ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+}
+
+/// Positions of the columns the fast path reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastColumns {
+    tx_type: usize,
+    client: usize,
+    tx: usize,
+    amount: usize,
+    timestamp: Option<usize>,
+}
+
+impl FastColumns {
+    /// None when a column is missing, repeated or one only serde handles (`currency`, `reason`)
+    pub fn new(headers: &ByteRecord) -> Option<Self> {
+        let mut columns = [None; 5];
+        for (index, header) in headers.iter().enumerate() {
+            let slot = match header {
+                b"type" => 0,
+                b"client" => 1,
+                b"tx" => 2,
+                b"amount" => 3,
+                b"timestamp" => 4,
+                _ => return None,
+            };
+            if columns[slot].replace(index).is_some() {
+                return None;
+            }
+        }
+
+        Some(Self { tx_type: columns[0]?, client: columns[1]?, tx: columns[2]?, amount: columns[3]?, timestamp: columns[4] })
+    }
+}
+
+// plain ASCII digits only, anything else is left to serde
+fn parse_digits<T: TryFrom<u64>>(bytes: &[u8]) -> Option<T> {
+    if bytes.is_empty() || bytes.len() > 19 || !bytes.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let value = bytes.iter().fold(0u64, |value, digit| value * 10 + u64::from(digit - b'0'));
+    T::try_from(value).ok()
+}
+
+/// Parse a record straight from its bytes, without allocating.
+/// None when a field is not in the plain form the fast path reads, the record must then go through serde,
+/// which either accepts it or reports the error. A record the fast path accepts deserializes to the same value.
+pub fn parse_record(record: &ByteRecord, columns: &FastColumns) -> Option<CsvTransaction> {
+    let tx_type = match record.get(columns.tx_type)? {
+        b"deposit" => TransactionType::Deposit,
+        b"withdrawal" => TransactionType::Withdrawal,
+        b"dispute" => TransactionType::Dispute,
+        b"resolve" => TransactionType::Resolve,
+        b"chargeback" => TransactionType::Chargeback,
+        b"refund" => TransactionType::Refund,
+        b"unlock" => TransactionType::Unlock,
+        b"freeze" => TransactionType::Freeze,
+        b"close" => TransactionType::Close,
+        b"reopen" => TransactionType::Reopen,
+        _ => return None,
+    };
+    let amount = match record.get(columns.amount)? {
+        b"" => None,
+        amount => Some(parse_amount(std::str::from_utf8(amount).ok()?).ok()?),
+    };
+    let timestamp = match columns.timestamp.map(|index| record.get(index)).unwrap_or(Some(b""))? {
+        b"" => None,
+        timestamp => Some(DateTime::parse_from_rfc3339(std::str::from_utf8(timestamp).ok()?).ok()?.with_timezone(&Utc)),
+    };
+
+    Some(CsvTransaction {
+        tx_type,
+        client_id: parse_digits(record.get(columns.client)?)?,
+        tx_id: parse_digits(record.get(columns.tx)?)?,
+        amount,
+        timestamp,
+        currency: None,
+        reason: None,
+        conversion: None,
+    })
+}
+
+/// Transactions of a CSV file in file order, read with `csv_reader` into one reused record.
+/// Rows go through the fast path where the columns and the row allow it, and otherwise are deserialized
+/// exactly as `csv::Reader::deserialize` would, with the same errors.
+pub struct Transactions<R> {
+    reader: csv::Reader<R>,
+    headers: Option<StringRecord>,
+    columns: Option<FastColumns>,
+    record: ByteRecord,
+}
+
+impl<R: Read> Transactions<R> {
+    pub fn new(reader: R) -> Self {
+        let mut reader = csv_reader(reader);
+        // as with `csv::Reader::deserialize`, headers that cannot be read (e.g. not UTF-8) leave rows to be read by position
+        let headers = reader.headers().ok().cloned();
+        let columns = headers.as_ref().and_then(|headers| FastColumns::new(headers.as_byte_record()));
+        Self { reader, headers, columns, record: ByteRecord::new() }
+    }
+
+    /// Bytes of the file read so far, header included
+    pub fn bytes_read(&self) -> u64 {
+        self.reader.position().byte()
+    }
+
+    // the row as `csv::Reader::deserialize` sees it: UTF-8 checked, then trimmed of Unicode whitespace as well
+    fn deserialize(&self, position: &Position) -> Result<CsvTransaction, csv::Error> {
+        match StringRecord::from_byte_record(self.record.clone()) {
+            Ok(mut record) => {
+                record.trim();
+                record.deserialize(self.headers.as_ref())
+            }
+            Err(e) => Err(InvalidUtf8 { position: position.clone(), error: e.utf8_error().clone() }.into()),
+        }
+    }
+}
+
+/// A row that is not valid UTF-8. csv cannot build its own UTF-8 error from outside the crate, its text is kept.
+#[derive(Debug)]
+struct InvalidUtf8 {
+    position: Position,
+    error: csv::Utf8Error,
+}
+
+impl fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "CSV parse error: record {} (line {}, field: {}, byte: {}): {}",
+            self.position.record(), self.position.line(), self.error.field(), self.position.byte(), self.error
+        )
+    }
+}
+
+impl std::error::Error for InvalidUtf8 {}
+
+impl From<InvalidUtf8> for csv::Error {
+    fn from(e: InvalidUtf8) -> Self {
+        csv::Error::from(io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// `e` as read that many records, lines and bytes further into the file, for input that does not start where the file does.
+/// The text stays that of csv's own error for the shifted position.
+pub(crate) fn shift_error(e: csv::Error, records: u64, lines: u64, bytes: u64) -> csv::Error {
+    let shift = |position: &Position| {
+        let mut shifted = Position::new();
+        shifted
+            .set_record(position.record() + records)
+            .set_line(position.line() + lines)
+            .set_byte(position.byte() + bytes);
+        shifted
+    };
+
+    let text = match e.kind() {
+        csv::ErrorKind::Utf8 { pos: Some(position), err } => return InvalidUtf8 { position: shift(position), error: err.clone() }.into(),
+        csv::ErrorKind::Io(io_error) => match io_error.get_ref().and_then(|inner| inner.downcast_ref::<InvalidUtf8>()) {
+            Some(invalid) => return InvalidUtf8 { position: shift(&invalid.position), error: invalid.error.clone() }.into(),
+            None => return e,
+        },
+        csv::ErrorKind::Deserialize { pos: Some(position), err } => {
+            let position = shift(position);
+            format!("CSV deserialize error: record {} (line: {}, byte: {}): {}", position.record(), position.line(), position.byte(), err)
+        }
+        csv::ErrorKind::UnequalLengths { pos: Some(position), expected_len, len } => {
+            let position = shift(position);
+            format!(
+                "CSV error: record {} (line: {}, byte: {}): found record with {} fields, but the previous record has {} fields",
+                position.record(), position.line(), position.byte(), len, expected_len
+            )
+        }
+        _ => return e,
+    };
+    csv::Error::from(io::Error::new(io::ErrorKind::InvalidData, text))
+}
+
+impl<R: Read> Iterator for Transactions<R> {
+    type Item = Result<CsvTransaction, csv::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let position = self.reader.position().clone();
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e)),
+        }
+
+        // every column of a row the fast path reads is valid UTF-8 without surrounding whitespace, serde reads it the same way
+        match self.columns.as_ref().and_then(|columns| parse_record(&self.record, columns)) {
+            Some(csv_transaction) => Some(Ok(csv_transaction)),
+            None => Some(self.deserialize(&position)),
+        }
+    }
+}
+
+/// Rows read before they are sent on as one batch, unless configured otherwise
+pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// What reading a CSV file came to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestionStats {
+    /// Rows deserialized, rejected ones included
+    pub rows_read: u64,
+    /// Rows read but not sent on, e.g. for lack of a usable exchange rate
+    pub rows_rejected: u64,
+    /// Bytes of the file read, header included
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+impl fmt::Display for IngestionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rows read, {} rejected, {} bytes in {:.3}s", self.rows_read, self.rows_rejected, self.bytes, self.duration.as_secs_f64())
+    }
+}
+
+impl IngestionStats {
+    /// The stats of a read started at `started`, with the error that stopped it early if any
+    pub(crate) fn finish(mut self, started: Instant, result: Result<(), ApplicationError>) -> Result<IngestionStats, IngestionError> {
+        self.duration = started.elapsed();
+        result.map(|()| self).map_err(|error| IngestionError { stats: self, error })
+    }
+}
+
+/// A read stopped before the end of its file, with how far it got
+#[derive(Debug)]
+pub struct IngestionError {
+    /// Rows and bytes read before the row or failure that stopped it
+    pub stats: IngestionStats,
+    pub error: ApplicationError,
+}
+
+impl fmt::Display for IngestionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (after {})", self.error, self.stats)
+    }
+}
+
+/// Read CSV in a streaming fashion and return what was read.
+/// Rows are sent in batches of up to `batch_size`, as whatever the dispatcher side takes, blocking while its channel is full.
+/// Nothing is kept once sent, memory stays the same whatever the size of the file.
+/// Rows read before a malformed one are still sent, and counted in the stats of the error.
+/// With `exchange_rates`, foreign-currency rows are converted to the base currency before dispatch
+/// and rows without a usable rate are skipped.
+pub fn read_csv<T: From<Vec<CsvTransaction>>>(csv_path: &str, dispatcher_sender: SyncSender<T>, exchange_rates: Option<&ExchangeRates>, batch_size: usize) -> Result<IngestionStats, IngestionError> {
+    let started = Instant::now();
+    let mut stats = IngestionStats::default();
+    let result = read_rows(csv_path, dispatcher_sender, exchange_rates, batch_size, &mut stats);
+    stats.finish(started, result)
+}
+
+fn read_rows<T: From<Vec<CsvTransaction>>>(csv_path: &str, dispatcher_sender: SyncSender<T>, exchange_rates: Option<&ExchangeRates>, batch_size: usize, stats: &mut IngestionStats) -> Result<(), ApplicationError> {
     let file = File::open(csv_path)
         .map_err(|e| ApplicationError::CouldNotOpenFile(format!("{}: {}", csv_path, e)))?;
 
-    let mut csv_reader = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_reader(file);
+    let mut transactions_iter = Transactions::new(file);
+    let batch_size = batch_size.max(1);
+    let send = |batch: Vec<CsvTransaction>| dispatcher_sender
+        .send(batch.into())
+        .map_err(|e| ApplicationError::Other(format!("Dispatcher channel closed: {}", e)));
+
+    let mut batch = Vec::with_capacity(batch_size);
+
+    loop {
+        // the bytes of the rows read so far, a malformed row's own bytes are not counted
+        stats.bytes = transactions_iter.bytes_read();
+        let Some(result) = transactions_iter.next() else { break };
+        let mut csv_transaction = match result {
+            Ok(csv_transaction) => csv_transaction,
+            Err(e) => {
+                if !batch.is_empty() {
+                    send(batch)?;
+                }
+                return Err(ApplicationError::FailedDeserializedCsvTransaction(format!("{}: {}", csv_path, e)));
+            }
+        };
+        stats.rows_read += 1;
+
+        if let Some(exchange_rates) = exchange_rates
+            && let Err(e) = exchange_rates.convert(&mut csv_transaction)
+        {
+            eprintln!("WARNING: skipping row, {}", e);
+            stats.rows_rejected += 1;
+            continue;
+        }
 
+        batch.push(csv_transaction);
+        if batch.len() == batch_size {
+            send(std::mem::replace(&mut batch, Vec::with_capacity(batch_size)))?;
+        }
+    }
+    stats.bytes = transactions_iter.bytes_read();
 
-    // this is not necessary, using for unit testing
-    let mut transactions = Vec::new();
+    if !batch.is_empty() {
+        send(batch)?;
+    }
+    Ok(())
+}
 
-    for result in csv_reader.deserialize() {
-        let csv_transaction: CsvTransaction = result
-            .map_err(|e| ApplicationError::FailedDeserializedCsvTransaction(format!("{}: {}", csv_path, e)))?;
+/// Stands in for the dispatcher in tests: a small bounded channel drained on its own thread,
+/// so readers block on it as they would in the pipeline and every batch they send can be looked at afterwards
+#[cfg(test)]
+pub(crate) struct TestSink(std::thread::JoinHandle<Vec<Vec<CsvTransaction>>>);
 
-        transactions.push(csv_transaction.clone());
-        dispatcher_sender
-            .send(csv_transaction)
-            .map_err(|e| ApplicationError::Other(format!("Dispatcher channel closed: {}", e)))?;
+#[cfg(test)]
+impl TestSink {
+    pub(crate) fn new() -> (SyncSender<Vec<CsvTransaction>>, Self) {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(2);
+        (sender, Self(std::thread::spawn(move || receiver.into_iter().collect())))
+    }
+
+    /// Batches in the order they were sent, once the sender is dropped
+    pub(crate) fn batches(self) -> Vec<Vec<CsvTransaction>> {
+        self.0.join().unwrap()
     }
 
-    Ok(transactions)
+    pub(crate) fn rows(self) -> Vec<CsvTransaction> {
+        self.batches().concat()
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use std::sync::mpsc::channel;
     use super::*;
 
     #[test]
     fn test_read_csv_real_file() {
 
         let path = "tests/transactions.csv"; // your real CSV file path
-        let (dispatcher_sender, _dispatcher_receiver) = channel::<CsvTransaction>();
+        let (dispatcher_sender, sink) = TestSink::new();
 
-        let transactions = read_csv(path, dispatcher_sender).expect("Failed to read CSV");
+        read_csv(path, dispatcher_sender, None, DEFAULT_BATCH_SIZE).expect("Failed to read CSV");
+        let transactions = sink.rows();
 
         // Check that we actually read transactions
         assert!(!transactions.is_empty(), "CSV should have at least one transaction");
@@ -104,22 +484,33 @@ mod tests {
                 TransactionType::Withdrawal |
                 TransactionType::Dispute |
                 TransactionType::Resolve |
-                TransactionType::Chargeback => {}
+                TransactionType::Chargeback |
+                TransactionType::Refund |
+                TransactionType::Unlock |
+                TransactionType::Freeze |
+                TransactionType::Close |
+                TransactionType::Reopen => {}
             }
 
             // Amount rules
             match tx.tx_type {
                 TransactionType::Deposit | TransactionType::Withdrawal => {
-                    let amount = tx.amount.expect(&format!("Withdrawl or Deposit type {} should have amount", row_number));
+                    let amount = tx.amount.unwrap_or_else(|| panic!("Withdrawl or Deposit type {} should have amount", row_number));
 
 
                     let amount_rounded = amount.round_dp(4);
                     //println!("amount {} and amount_rounded {} are:",tx.amount.unwrap(), amount_rounded);
                     assert_eq!(amount, amount_rounded, "Withdraw or Deposit type at Row {} amount not rounded to 4 decimals", row_number);
                 }
+                // a refund without amount returns whatever is left of the deposit
+                TransactionType::Refund => {}
                 TransactionType::Dispute |
                 TransactionType::Resolve |
-                TransactionType::Chargeback => {
+                TransactionType::Chargeback |
+                TransactionType::Unlock |
+                TransactionType::Freeze |
+                TransactionType::Close |
+                TransactionType::Reopen => {
                     assert!(
                         tx.amount.is_none(),
                         "Transaction type {:?} at Row {} should not have an amount",
@@ -135,13 +526,228 @@ mod tests {
     #[test]
     fn test_read_csv_malformed_file() {
         let path = "tests/malformed.csv"; // a deliberately bad CSV
-        let (dispatcher_sender, _dispatcher_receiver) = channel::<CsvTransaction>();
+        let (dispatcher_sender, _sink) = TestSink::new();
 
-        let err = read_csv(path, dispatcher_sender).unwrap_err();
+        let err = read_csv(path, dispatcher_sender, None, DEFAULT_BATCH_SIZE).unwrap_err();
 
-        match err {
+        match err.error {
             ApplicationError::FailedDeserializedCsvTransaction(_) => (),
             _ => panic!("Expected FailedDeserializedCsvTransaction error"),
         }
+        // the header and the row before the malformed one
+        assert_eq!(err.stats.rows_read, 1);
+        assert_eq!(err.stats.bytes, "type,client,tx,amount\ndeposit,1,1001,100.21466\n".len() as u64);
+    }
+
+    #[test]
+    fn test_read_csv_batches() {
+        let (dispatcher_sender, sink) = TestSink::new();
+        let stats = read_csv("tests/transactions.csv", dispatcher_sender, None, 4).expect("Failed to read CSV");
+
+        let batches = sink.batches();
+        assert!(batches.len() > 1);
+        assert!(batches.iter().all(|batch| (1..=4).contains(&batch.len())));
+        assert!(batches[..batches.len() - 1].iter().all(|batch| batch.len() == 4));
+        assert_eq!(batches.concat().len() as u64, stats.rows_read);
+
+        // rows before a malformed one still go out
+        let (dispatcher_sender, sink) = TestSink::new();
+        read_csv("tests/malformed.csv", dispatcher_sender, None, 4).unwrap_err();
+        let sent: Vec<u32> = sink.rows().iter().map(|tx| tx.tx_id).collect();
+        assert_eq!(sent, vec![1001]);
+    }
+
+    #[test]
+    fn test_read_csv_stats() {
+        let (dispatcher_sender, sink) = TestSink::new();
+        let stats = read_csv("tests/transactions.csv", dispatcher_sender, None, DEFAULT_BATCH_SIZE).expect("Failed to read CSV");
+
+        assert_eq!(stats.rows_read, sink.rows().len() as u64);
+        assert_eq!(stats.rows_rejected, 0);
+        assert_eq!(stats.bytes, std::fs::metadata("tests/transactions.csv").unwrap().len());
+
+        // rows without a usable rate into USD are read but not sent on
+        let exchange_rates = ExchangeRates::load("tests/rates.csv", "USD".parse().unwrap()).unwrap();
+        let (dispatcher_sender, sink) = TestSink::new();
+        let stats = read_csv("tests/currencies.csv", dispatcher_sender, Some(&exchange_rates), DEFAULT_BATCH_SIZE).expect("Failed to read CSV");
+
+        assert_eq!(stats.rows_read, 6);
+        assert!(stats.rows_rejected > 0);
+        assert_eq!(stats.rows_read - stats.rows_rejected, sink.rows().len() as u64);
+    }
+
+    #[test]
+    fn test_read_csv_rejects_conversion_overflow() {
+        let path = std::env::temp_dir().join(format!("drizzly-overflow-{}.csv", std::process::id()));
+        std::fs::write(&path, "type,client,tx,amount,currency\n\
+            deposit,1,1,79228162514264337593543950335,GBP\n\
+            deposit,1,2,10.0,GBP\n").unwrap();
+        let exchange_rates = ExchangeRates::load("tests/rates.csv", "EUR".parse().unwrap()).unwrap();
+
+        let (dispatcher_sender, sink) = TestSink::new();
+        let stats = read_csv(path.to_str().unwrap(), dispatcher_sender, Some(&exchange_rates), DEFAULT_BATCH_SIZE);
+        let _ = std::fs::remove_file(&path);
+
+        let stats = stats.expect("Failed to read CSV");
+        assert_eq!((stats.rows_read, stats.rows_rejected), (2, 1));
+        assert_eq!(sink.rows().iter().map(|tx| tx.tx_id).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn test_read_csv_with_timestamps() {
+        let path = "tests/timestamps.csv";
+        let (dispatcher_sender, sink) = TestSink::new();
+
+        read_csv(path, dispatcher_sender, None, DEFAULT_BATCH_SIZE).expect("Failed to read CSV");
+        let transactions = sink.rows();
+
+        // offsets are normalised to UTC
+        let first = transactions[0].timestamp.expect("first row should have a timestamp");
+        assert_eq!(first.to_rfc3339(), "2026-03-01T08:00:00+00:00");
+        let second = transactions[1].timestamp.expect("second row should have a timestamp");
+        assert_eq!(second.to_rfc3339(), "2026-03-01T09:30:00+00:00");
+
+        // the column is optional per row
+        assert!(transactions.iter().any(|tx| tx.timestamp.is_none()));
+    }
+
+    #[test]
+    fn test_read_csv_admin_operations() {
+        let path = "tests/admin.csv";
+        let (dispatcher_sender, sink) = TestSink::new();
+
+        read_csv(path, dispatcher_sender, None, DEFAULT_BATCH_SIZE).expect("Failed to read CSV");
+        let transactions = sink.rows();
+
+        let admin: Vec<&CsvTransaction> = transactions.iter().filter(|tx| tx.tx_type.is_admin()).collect();
+        assert_eq!(admin.len(), 2);
+        assert!(matches!(admin[0].tx_type, TransactionType::Unlock));
+        assert_eq!(admin[0].reason.as_deref(), Some("chargeback reversed by bank"));
+
+        // reason is optional at parse time
+        assert!(transactions.iter().filter(|tx| !tx.tx_type.is_admin()).all(|tx| tx.reason.is_none()));
+    }
+
+    #[test]
+    fn test_read_csv_with_currencies() {
+        let path = "tests/currencies.csv";
+        let (dispatcher_sender, sink) = TestSink::new();
+
+        read_csv(path, dispatcher_sender, None, DEFAULT_BATCH_SIZE).expect("Failed to read CSV");
+        let transactions = sink.rows();
+
+        let codes: Vec<Option<&str>> = transactions.iter().map(|tx| tx.currency.as_ref().map(Currency::code)).collect();
+        assert_eq!(codes, vec![Some("EUR"), Some("USD"), Some("GBP"), Some("EUR"), None, None]);
+    }
+
+    #[test]
+    fn test_read_csv_converts_to_base_currency() {
+        let path = "tests/currencies.csv";
+        let (dispatcher_sender, sink) = TestSink::new();
+        let exchange_rates = ExchangeRates::load("tests/rates.csv", "EUR".parse().unwrap()).unwrap();
+
+        read_csv(path, dispatcher_sender, Some(&exchange_rates), DEFAULT_BATCH_SIZE).expect("Failed to read CSV");
+        let transactions = sink.rows();
+
+        // every row with an amount and a currency is now in EUR
+        for tx in transactions.iter().filter(|tx| tx.amount.is_some() && tx.currency.is_some()) {
+            assert_eq!(tx.currency.as_ref().map(Currency::code), Some("EUR"));
+        }
+
+        let usd_deposit = transactions.iter().find(|tx| tx.tx_id == 2).unwrap();
+        assert_eq!(usd_deposit.amount, Some(Decimal::from(40)));
+        let conversion = usd_deposit.conversion.as_ref().expect("conversion should be recorded");
+        assert_eq!(conversion.original_amount, Decimal::from(50));
+        assert_eq!(conversion.original_currency.code(), "USD");
+
+        // EUR rows are untouched
+        assert!(transactions.iter().find(|tx| tx.tx_id == 1).unwrap().conversion.is_none());
+    }
+
+    #[test]
+    fn test_read_csv_refunds() {
+        let path = "tests/refunds.csv";
+        let (dispatcher_sender, sink) = TestSink::new();
+
+        read_csv(path, dispatcher_sender, None, DEFAULT_BATCH_SIZE).expect("Failed to read CSV");
+        let transactions = sink.rows();
+
+        let refunds: Vec<(u32, Option<Decimal>)> = transactions.iter()
+            .filter(|tx| tx.tx_type == TransactionType::Refund)
+            .map(|tx| (tx.tx_id, tx.amount))
+            .collect();
+        assert_eq!(refunds, vec![(1, Some(Decimal::new(255, 1))), (3, Some(Decimal::from(10))), (1, None)]);
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("100.21466").unwrap(), Decimal::new(1002147, 4));
+        assert_eq!(parse_amount("1").unwrap(), Decimal::ONE);
+        assert!(parse_amount("").is_err());
+        assert!(parse_amount("1e5").is_err());
+    }
+
+    // what serde makes of the same input, errors as text
+    fn deserialize_all(csv: &[u8]) -> Vec<Result<CsvTransaction, String>> {
+        csv_reader(csv).deserialize().map(|row| row.map_err(|e: csv::Error| e.to_string())).collect()
+    }
+
+    fn read_all(csv: &[u8]) -> Vec<Result<CsvTransaction, String>> {
+        Transactions::new(csv).map(|row| row.map_err(|e| e.to_string())).collect()
+    }
+
+    #[test]
+    fn test_fast_path_matches_serde() {
+        for entry in std::fs::read_dir("tests").unwrap() {
+            let path = entry.unwrap().path();
+            let csv = std::fs::read(&path).unwrap();
+            assert_eq!(read_all(&csv), deserialize_all(&csv), "{}", path.display());
+        }
+
+        // rows the fast path leaves to serde, accepted or rejected there
+        let edge_cases = "type,client,tx,amount,timestamp\n\
+            deposit,+5,1,1.0,\n\
+            deposit,70000,1,1.0,\n\
+            deposit,1,99999999999999999999,1.0,\n\
+            deposit,1,-1,1.0,\n\
+            deposit,1,2,1e5,\n\
+            deposit,1,3,1.23456789,\n\
+            deposit,1,4,,not a time\n\
+            Deposit,1,5,1,\n\
+            withdrawal,007,6,2.5,2026-03-01T08:00:00+02:00\n";
+        assert_eq!(read_all(edge_cases.as_bytes()), deserialize_all(edge_cases.as_bytes()));
+        // headers that are not UTF-8 are ignored, fields are taken by position
+        let by_position = b"type,cl\xffient,tx,amount\ndeposit,1,2,3.0,\n";
+        assert_eq!(read_all(by_position), deserialize_all(by_position));
+        // a row that is not UTF-8 is rejected even where the field is not read
+        let unread_field = b"type,client,tx,amount,note\ndeposit,1,2,3.0,\xff\n";
+        assert_eq!(read_all(unread_field), deserialize_all(unread_field));
+        let invalid_amount = b"type,client,tx,amount\ndeposit,1,2,3.\xff\n";
+        assert_eq!(read_all(invalid_amount), deserialize_all(invalid_amount));
+        // serde also trims Unicode whitespace
+        let unicode_space = "type,client,tx,amount\ndeposit,1\u{a0},2,3.0\u{2003}\n";
+        assert_eq!(read_all(unicode_space.as_bytes()), deserialize_all(unicode_space.as_bytes()));
+
+        let headers = ByteRecord::from(vec!["type", "client", "tx", "amount"]);
+        let columns = FastColumns::new(&headers).expect("plain columns");
+        let plain = ByteRecord::from(vec!["deposit", "1", "7", "1.23456"]);
+        let parsed = parse_record(&plain, &columns).expect("plain row");
+        assert_eq!((parsed.client_id, parsed.tx_id, parsed.amount), (1, 7, Some(Decimal::new(12346, 4))));
+        assert!(parse_record(&ByteRecord::from(vec!["deposit", "+1", "7", "1"]), &columns).is_none());
+
+        // serde handles currencies and reasons
+        assert!(FastColumns::new(&ByteRecord::from(vec!["type", "client", "tx", "amount", "currency"])).is_none());
+        assert!(FastColumns::new(&ByteRecord::from(vec!["type", "client", "tx", "tx"])).is_none());
+    }
+
+    #[test]
+    fn test_read_csv_without_timestamp_column() {
+        let path = "tests/transactions.csv";
+        let (dispatcher_sender, sink) = TestSink::new();
+
+        read_csv(path, dispatcher_sender, None, DEFAULT_BATCH_SIZE).expect("Failed to read CSV");
+        let transactions = sink.rows();
+
+        assert!(transactions.iter().all(|tx| tx.timestamp.is_none()));
     }
 }
\ No newline at end of file
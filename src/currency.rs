@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use csv::ReaderBuilder;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use crate::csv_ingestor::CsvTransaction;
+use crate::error::ApplicationError;
+
+/// Rows without a currency are booked in this one unless configured otherwise
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+/// ISO 4217 style currency code, three upper-case ASCII letters
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency(String);
+
+impl Currency {
+    pub fn code(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Currency(DEFAULT_CURRENCY.to_string())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = ApplicationError;
+
+    /// Codes are case-insensitive on input and stored upper-case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() == 3 && s.chars().all(|c| c.is_ascii_alphabetic()) {
+            Ok(Currency(s.to_ascii_uppercase()))
+        } else {
+            Err(ApplicationError::InvalidCurrency(s.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// How a foreign-currency row was brought into the base currency, kept with the row in `tx_history`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conversion {
+    pub original_amount: Decimal,
+    pub original_currency: Currency,
+    pub rate: Decimal,
+    /// Exact converted amount minus the booked (rounded) amount
+    pub residue: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateRow {
+    pair: String,
+    timestamp: String,
+    rate: String,
+}
+
+/// Exchange rates into a single base currency, loaded once at startup
+#[derive(Debug)]
+pub struct ExchangeRates {
+    base: Currency,
+    // rates from a currency into base, sorted by effective time
+    rates: HashMap<Currency, Vec<(DateTime<Utc>, Decimal)>>,
+}
+
+impl ExchangeRates {
+    /// Load a CSV of `pair,timestamp,rate` rows, e.g. `GBP/EUR,2026-03-01T00:00:00Z,1.1712` meaning 1 GBP = 1.1712 EUR.
+    /// Pairs quoted the other way round (`EUR/GBP`) are inverted, pairs not involving `base` are ignored.
+    pub fn load(path: &str, base: Currency) -> Result<Self, ApplicationError> {
+        let file = File::open(path)
+            .map_err(|e| ApplicationError::CouldNotOpenFile(format!("{}: {}", path, e)))?;
+
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(file);
+
+        let invalid = |row: &RateRow, reason: String| ApplicationError::InvalidConfiguration(
+            format!("{}: rate row {}: {}", path, row.pair, reason)
+        );
+
+        let mut rates: HashMap<Currency, Vec<(DateTime<Utc>, Decimal)>> = HashMap::new();
+        for result in reader.deserialize() {
+            let row: RateRow = result
+                .map_err(|e| ApplicationError::InvalidConfiguration(format!("{}: {}", path, e)))?;
+
+            let (from, to) = row.pair.split_once('/')
+                .ok_or_else(|| invalid(&row, "pair should look like GBP/EUR".to_string()))?;
+            let (from, to): (Currency, Currency) = (from.parse()?, to.parse()?);
+            let timestamp = DateTime::parse_from_rfc3339(&row.timestamp)
+                .map_err(|e| invalid(&row, e.to_string()))?
+                .with_timezone(&Utc);
+            let rate = Decimal::from_str(&row.rate)
+                .ok()
+                .filter(|rate| *rate > Decimal::ZERO)
+                .ok_or_else(|| invalid(&row, format!("invalid rate '{}'", row.rate)))?;
+
+            if to == base {
+                rates.entry(from).or_default().push((timestamp, rate));
+            } else if from == base {
+                rates.entry(to).or_default().push((timestamp, Decimal::ONE / rate));
+            }
+        }
+
+        for history in rates.values_mut() {
+            history.sort_by_key(|(timestamp, _)| *timestamp);
+        }
+
+        Ok(Self { base, rates })
+    }
+
+    pub fn base(&self) -> &Currency {
+        &self.base
+    }
+
+    /// Rate from `currency` into base effective at `at`, the latest known one when `at` is None
+    pub fn rate(&self, currency: &Currency, at: Option<DateTime<Utc>>) -> Option<Decimal> {
+        if *currency == self.base {
+            return Some(Decimal::ONE);
+        }
+
+        let history = self.rates.get(currency)?;
+        match at {
+            Some(at) => {
+                let effective = history.partition_point(|(timestamp, _)| *timestamp <= at);
+                effective.checked_sub(1).map(|i| history[i].1)
+            }
+            None => history.last().map(|(_, rate)| *rate),
+        }
+    }
+
+    /// Rewrite a foreign-currency row into the base currency, rounded to 4 decimal places like parsed amounts.
+    /// Rows without an amount or currency, or already in base, are left alone.
+    pub fn convert(&self, tx: &mut CsvTransaction) -> Result<(), ApplicationError> {
+        let (Some(amount), Some(currency)) = (tx.amount, tx.currency.as_ref()) else {
+            return Ok(());
+        };
+        if *currency == self.base {
+            return Ok(());
+        }
+
+        let rate = self.rate(currency, tx.timestamp)
+            .ok_or_else(|| ApplicationError::MissingExchangeRate(tx.client_id, tx.tx_id, currency.clone()))?;
+        let exact = amount.checked_mul(rate)
+            .ok_or_else(|| ApplicationError::ConversionOverflow(tx.client_id, tx.tx_id, currency.clone()))?;
+        let converted = exact.round_dp(4);
+
+        tx.conversion = Some(Conversion {
+            original_amount: amount,
+            original_currency: currency.clone(),
+            rate,
+            residue: exact - converted,
+        });
+        tx.amount = Some(converted);
+        tx.currency = Some(self.base.clone());
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_ingestor::TransactionType;
+
+    fn at(t: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(t).unwrap().with_timezone(&Utc)
+    }
+
+    fn eur() -> Currency {
+        "EUR".parse().unwrap()
+    }
+
+    fn gbp_deposit(amount: &str, timestamp: Option<&str>) -> CsvTransaction {
+        CsvTransaction {
+            tx_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id: 7,
+            amount: Some(Decimal::from_str(amount).unwrap()),
+            timestamp: timestamp.map(at),
+            currency: Some("GBP".parse().unwrap()),
+            reason: None,
+            conversion: None,
+        }
+    }
+
+    #[test]
+    fn test_rate_lookup_by_time() {
+        let rates = ExchangeRates::load("tests/rates.csv", eur()).unwrap();
+        let gbp: Currency = "GBP".parse().unwrap();
+        let usd: Currency = "USD".parse().unwrap();
+
+        assert_eq!(rates.rate(&eur(), None), Some(Decimal::ONE));
+        assert_eq!(rates.rate(&gbp, Some(at("2026-03-01T12:00:00Z"))), Some(Decimal::from_str("1.17").unwrap()));
+        assert_eq!(rates.rate(&gbp, Some(at("2026-03-02T12:00:00Z"))), Some(Decimal::from_str("1.18").unwrap()));
+        assert_eq!(rates.rate(&gbp, None), Some(Decimal::from_str("1.18").unwrap()));
+        // before the first quote there is no rate
+        assert_eq!(rates.rate(&gbp, Some(at("2026-02-01T00:00:00Z"))), None);
+        // EUR/USD is quoted the other way round
+        assert_eq!(rates.rate(&usd, None), Some(Decimal::ONE / Decimal::from_str("1.25").unwrap()));
+    }
+
+    #[test]
+    fn test_convert_records_original_and_residue() {
+        let rates = ExchangeRates::load("tests/rates.csv", eur()).unwrap();
+
+        let mut tx = gbp_deposit("10.0001", Some("2026-03-01T12:00:00Z"));
+        rates.convert(&mut tx).unwrap();
+
+        // 10.0001 * 1.17 = 11.700117
+        assert_eq!(tx.amount, Some(Decimal::from_str("11.7001").unwrap()));
+        assert_eq!(tx.currency, Some(eur()));
+        let conversion = tx.conversion.expect("conversion should be recorded");
+        assert_eq!(conversion.original_amount, Decimal::from_str("10.0001").unwrap());
+        assert_eq!(conversion.original_currency.code(), "GBP");
+        assert_eq!(conversion.rate, Decimal::from_str("1.17").unwrap());
+        assert_eq!(conversion.residue, Decimal::from_str("0.000017").unwrap());
+    }
+
+    #[test]
+    fn test_convert_without_rate() {
+        let rates = ExchangeRates::load("tests/rates.csv", eur()).unwrap();
+
+        let mut tx = gbp_deposit("10", Some("2026-01-01T00:00:00Z"));
+        match rates.convert(&mut tx).unwrap_err() {
+            ApplicationError::MissingExchangeRate(client_id, tx_id, currency) => {
+                assert_eq!(client_id, 1);
+                assert_eq!(tx_id, 7);
+                assert_eq!(currency.code(), "GBP");
+            }
+            _ => panic!("Expected MissingExchangeRate error"),
+        }
+    }
+
+    #[test]
+    fn test_convert_overflow() {
+        let rates = ExchangeRates::load("tests/rates.csv", eur()).unwrap();
+
+        let mut tx = gbp_deposit("79228162514264337593543950335", Some("2026-03-01T12:00:00Z"));
+        assert!(matches!(rates.convert(&mut tx).unwrap_err(), ApplicationError::ConversionOverflow(1, 7, _)));
+        // the row is left as it was
+        assert_eq!(tx.currency.as_ref().map(Currency::code), Some("GBP"));
+        assert!(tx.conversion.is_none());
+    }
+
+    #[test]
+    fn test_parse_currency() {
+        assert_eq!("eur".parse::<Currency>().unwrap().code(), "EUR");
+        assert_eq!("GBP".parse::<Currency>().unwrap().code(), "GBP");
+        assert!("EURO".parse::<Currency>().is_err());
+        assert!("U$D".parse::<Currency>().is_err());
+        assert_eq!(Currency::default().code(), DEFAULT_CURRENCY);
+    }
+}
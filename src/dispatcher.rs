@@ -1,18 +1,40 @@
 
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
 use std::thread;
 use std::thread::JoinHandle;
 use crate::csv_ingestor::CsvTransaction;
 use crate::error::ApplicationError;
 
+/// Messages travelling from the ingestion side through the dispatcher to the workers
+#[derive(Debug)]
+pub enum Command {
+    Transaction(CsvTransaction),
+    /// Transactions in input order, applied by a worker under a single lock of the clients map
+    Batch(Vec<CsvTransaction>),
+    /// Apply everything sent before, including what sequencers hold back, then acknowledge once per worker
+    Flush(Sender<()>),
+}
+
+impl From<CsvTransaction> for Command {
+    fn from(csv_transaction: CsvTransaction) -> Self {
+        Command::Transaction(csv_transaction)
+    }
+}
+
+impl From<Vec<CsvTransaction>> for Command {
+    fn from(batch: Vec<CsvTransaction>) -> Self {
+        Command::Batch(batch)
+    }
+}
+
 /// Dispatcher forwards transactions to a worker assigned specifically to a client id
 pub struct Dispatcher {
-    worker_senders: Vec<Sender<CsvTransaction>>,
+    worker_senders: Vec<SyncSender<Command>>,
     num_workers: usize,
 }
 
 impl Dispatcher {
-    pub fn new(worker_senders: Vec<Sender<CsvTransaction>>) -> Self {
+    pub fn new(worker_senders: Vec<SyncSender<Command>>) -> Self {
         let num_workers = worker_senders.len();
         Self { worker_senders, num_workers }
     }
@@ -23,15 +45,46 @@ impl Dispatcher {
         (client_id as usize) % self.num_workers
     }
 
+    /// Split a batch into one sub-batch per worker, keeping the input order within each.
+    /// Workers without transactions get an empty sub-batch.
+    fn split(&self, batch: Vec<CsvTransaction>) -> Vec<Vec<CsvTransaction>> {
+        let mut sub_batches: Vec<Vec<CsvTransaction>> = (0..self.num_workers).map(|_| Vec::new()).collect();
+        for csv_transaction in batch {
+            sub_batches[self.assign_worker(csv_transaction.client_id)].push(csv_transaction);
+        }
+        sub_batches
+    }
+
     /// Start dispatcher loop in its own thread, select the right worker based on client_id
-    /// and send transactions to it
-    pub fn start(self, ingestion_receiver: Receiver<CsvTransaction>) ->  JoinHandle<Result<(), ApplicationError>> {
+    /// and send transactions to it. Batches are split per worker, flushes go to every worker.
+    pub fn start(self, ingestion_receiver: Receiver<Command>) ->  JoinHandle<Result<(), ApplicationError>> {
         thread::spawn(move || {
-            for csv_transaction in ingestion_receiver {
-                let worker_index = self.assign_worker(csv_transaction.client_id);
+            for command in ingestion_receiver {
+                match command {
+                    Command::Transaction(csv_transaction) => {
+                        let worker_index = self.assign_worker(csv_transaction.client_id);
 
-                if let Err(e) = self.worker_senders[worker_index].send(csv_transaction) {
-                    eprintln!("Dispatcher failed to send to worker {}: {}", worker_index, e);
+                        if let Err(e) = self.worker_senders[worker_index].send(Command::Transaction(csv_transaction)) {
+                            eprintln!("Dispatcher failed to send to worker {}: {}", worker_index, e);
+                        }
+                    }
+                    Command::Batch(batch) => {
+                        for (worker_index, sub_batch) in self.split(batch).into_iter().enumerate() {
+                            if sub_batch.is_empty() {
+                                continue;
+                            }
+                            if let Err(e) = self.worker_senders[worker_index].send(Command::Batch(sub_batch)) {
+                                eprintln!("Dispatcher failed to send to worker {}: {}", worker_index, e);
+                            }
+                        }
+                    }
+                    Command::Flush(ack) => {
+                        for (worker_index, worker_sender) in self.worker_senders.iter().enumerate() {
+                            if let Err(e) = worker_sender.send(Command::Flush(ack.clone())) {
+                                eprintln!("Dispatcher failed to send to worker {}: {}", worker_index, e);
+                            }
+                        }
+                    }
                 }
             }
 
@@ -39,3 +92,36 @@ impl Dispatcher {
         })
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+    use std::sync::mpsc::sync_channel;
+    use crate::csv_ingestor::TransactionType;
+
+
+    #[test]
+    fn test_batches_are_split_per_worker_in_order() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| sync_channel(8)).unzip();
+        let (ingestion_sender, ingestion_receiver) = sync_channel(8);
+        let handle = Dispatcher::new(senders).start(ingestion_receiver);
+
+        // clients 1 and 4 share worker 1, nothing goes to worker 0
+        let batch = vec![tx(TransactionType::Deposit, 1, 1), tx(TransactionType::Deposit, 2, 2), tx(TransactionType::Deposit, 4, 3), tx(TransactionType::Deposit, 1, 4), tx(TransactionType::Deposit, 2, 5)];
+        ingestion_sender.send(Command::from(batch)).unwrap();
+        drop(ingestion_sender);
+        handle.join().unwrap().unwrap();
+
+        let received: Vec<Vec<Vec<u32>>> = receivers.iter()
+            .map(|receiver| receiver.try_iter()
+                .map(|command| match command {
+                    Command::Batch(batch) => batch.iter().map(|tx| tx.tx_id).collect(),
+                    other => panic!("expected a batch, got {:?}", other),
+                })
+                .collect())
+            .collect();
+        assert_eq!(received, vec![vec![], vec![vec![1, 3, 4]], vec![vec![2, 5]]]);
+    }
+}
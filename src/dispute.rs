@@ -0,0 +1,251 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, TimeDelta, Utc};
+use rust_decimal::Decimal;
+use crate::config::parse_duration;
+use crate::currency::Currency;
+use crate::error::ApplicationError;
+
+/// What happens to a dispute that stays open longer than allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryAction {
+    Resolve,
+    Chargeback,
+}
+
+/// Disputes open longer than `max_open` (in event time) are settled with `action`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeExpiry {
+    pub max_open: TimeDelta,
+    pub action: ExpiryAction,
+}
+
+impl FromStr for DisputeExpiry {
+    type Err = ApplicationError;
+
+    /// Accepts `<duration>:resolve` or `<duration>:chargeback`, e.g. `30d:resolve`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (max_open, action) = s.split_once(':')
+            .ok_or_else(|| ApplicationError::InvalidConfiguration(
+                format!("dispute expiry '{}' should look like <duration>:resolve|chargeback", s)
+            ))?;
+
+        let action = match action {
+            "resolve" => ExpiryAction::Resolve,
+            "chargeback" => ExpiryAction::Chargeback,
+            other => return Err(ApplicationError::InvalidConfiguration(
+                format!("unknown dispute expiry action '{}'", other)
+            )),
+        };
+
+        Ok(DisputeExpiry { max_open: parse_duration(max_open)?, action })
+    }
+}
+
+/// A dispute waiting for its resolve or chargeback, covering all or part of the disputed tx
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenDispute {
+    pub raised_at: Option<DateTime<Utc>>,
+    pub currency: Currency,
+    /// Portion of the tx amount under dispute
+    pub amount: Decimal,
+    /// Amount moved to held, less than the disputed amount when the overdraft policy held only part of it
+    pub held: Decimal,
+}
+
+/// What to do when a dispute asks to hold more than the client has available,
+/// typically because the disputed funds were already withdrawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverdraftPolicy {
+    /// Hold the full amount and let available go negative
+    #[default]
+    AllowNegative,
+    /// Refuse the dispute
+    Reject,
+    /// Hold only what is available
+    HoldAvailable,
+    /// Hold as much as keeps available above `-overdraft_limit` and move the client to collections
+    Collections { overdraft_limit: Decimal },
+}
+
+impl FromStr for OverdraftPolicy {
+    type Err = ApplicationError;
+
+    /// Accepts `allow`, `reject`, `hold-available` or `collections:<overdraft_limit>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(OverdraftPolicy::AllowNegative),
+            "reject" => Ok(OverdraftPolicy::Reject),
+            "hold-available" => Ok(OverdraftPolicy::HoldAvailable),
+            _ => {
+                let overdraft_limit = s
+                    .strip_prefix("collections:")
+                    .and_then(|limit| Decimal::from_str(limit).ok())
+                    .filter(|limit| *limit >= Decimal::ZERO)
+                    .ok_or_else(|| ApplicationError::InvalidConfiguration(
+                        format!("unknown overdraft policy '{}', expected allow, reject, hold-available or collections:<limit>", s)
+                    ))?;
+                Ok(OverdraftPolicy::Collections { overdraft_limit })
+            }
+        }
+    }
+}
+
+impl OverdraftPolicy {
+    /// How far available may go below zero under this policy, only collections sets a limit
+    pub fn overdraft_limit(&self) -> Decimal {
+        match self {
+            OverdraftPolicy::Collections { overdraft_limit } => *overdraft_limit,
+            _ => Decimal::ZERO,
+        }
+    }
+}
+
+/// How the overdraft policy treated a dispute that exceeded the available balance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverdraftOutcome {
+    AllowedNegative { requested: Decimal, available: Decimal },
+    HeldAvailable { requested: Decimal, held: Decimal },
+    Collections { requested: Decimal, held: Decimal },
+}
+
+impl fmt::Display for OverdraftOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverdraftOutcome::AllowedNegative { requested, available } =>
+                write!(f, "held {} with only {} available, available is negative", requested, available),
+            OverdraftOutcome::HeldAvailable { requested, held } =>
+                write!(f, "held {} of {} requested", held, requested),
+            OverdraftOutcome::Collections { requested, held } =>
+                write!(f, "held {} of {} requested, client moved to collections", held, requested),
+        }
+    }
+}
+
+/// A client's dispute activity, reported when it triggers an automatic freeze
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisputeStats {
+    pub open_disputes: usize,
+    /// Amount under dispute over amount ever deposited, the highest across the client's currencies
+    pub disputed_ratio: Decimal,
+}
+
+/// Levels of dispute activity at which a client gets frozen, unset ones are not checked
+#[derive(Debug, Clone, Default)]
+pub struct AbuseThresholds {
+    pub max_open_disputes: Option<usize>,
+    pub max_disputed_ratio: Option<Decimal>,
+}
+
+impl AbuseThresholds {
+    /// Whether `stats` reach any of the thresholds
+    pub fn crossed(&self, stats: &DisputeStats) -> bool {
+        self.max_open_disputes.is_some_and(|max| stats.open_disputes >= max)
+            || self.max_disputed_ratio.is_some_and(|max| stats.disputed_ratio >= max)
+    }
+}
+
+/// Dispute rules applied by workers
+#[derive(Debug, Clone, Default)]
+pub struct DisputePolicy {
+    /// Disputes raised longer than this after the original transaction are rejected
+    pub window: Option<TimeDelta>,
+    pub expiry: Option<DisputeExpiry>,
+    pub abuse: AbuseThresholds,
+    pub overdraft: OverdraftPolicy,
+}
+
+/// Deadlines of open disputes for the clients of one worker, earliest first
+#[derive(Debug, Default)]
+pub struct ExpiryQueue {
+    deadlines: BinaryHeap<Reverse<(DateTime<Utc>, u16, u32)>>,
+}
+
+impl ExpiryQueue {
+    pub fn schedule(&mut self, deadline: DateTime<Utc>, client_id: u16, tx_id: u32) {
+        self.deadlines.push(Reverse((deadline, client_id, tx_id)));
+    }
+
+    /// Entries scheduled and not yet due
+    pub fn len(&self) -> usize {
+        self.deadlines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+
+    /// Pop every entry whose deadline has passed at `now`
+    pub fn due(&mut self, now: DateTime<Utc>) -> Vec<(DateTime<Utc>, u16, u32)> {
+        let mut due = Vec::new();
+        while let Some(Reverse(entry)) = self.deadlines.peek() {
+            if entry.0 >= now {
+                break;
+            }
+            due.push(*entry);
+            self.deadlines.pop();
+        }
+        due
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(t: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(t).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_parse_expiry() {
+        let expiry: DisputeExpiry = "30d:chargeback".parse().unwrap();
+        assert_eq!(expiry.max_open, TimeDelta::days(30));
+        assert_eq!(expiry.action, ExpiryAction::Chargeback);
+
+        assert!("30d".parse::<DisputeExpiry>().is_err());
+        assert!("30d:refund".parse::<DisputeExpiry>().is_err());
+    }
+
+    #[test]
+    fn test_parse_overdraft_policy() {
+        assert_eq!("allow".parse::<OverdraftPolicy>().unwrap(), OverdraftPolicy::AllowNegative);
+        assert_eq!("hold-available".parse::<OverdraftPolicy>().unwrap(), OverdraftPolicy::HoldAvailable);
+        assert_eq!(
+            "collections:250.5".parse::<OverdraftPolicy>().unwrap(),
+            OverdraftPolicy::Collections { overdraft_limit: Decimal::new(2505, 1) }
+        );
+        assert!("collections:-1".parse::<OverdraftPolicy>().is_err());
+        assert!("sometimes".parse::<OverdraftPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_abuse_thresholds() {
+        let thresholds = AbuseThresholds {
+            max_open_disputes: Some(3),
+            max_disputed_ratio: Some(Decimal::new(5, 1)),
+        };
+
+        let stats = |open_disputes, disputed_ratio| DisputeStats { open_disputes, disputed_ratio };
+        assert!(!thresholds.crossed(&stats(2, Decimal::new(4, 1))));
+        assert!(thresholds.crossed(&stats(3, Decimal::ZERO)));
+        assert!(thresholds.crossed(&stats(1, Decimal::new(5, 1))));
+        assert!(!AbuseThresholds::default().crossed(&stats(100, Decimal::ONE)));
+    }
+
+    #[test]
+    fn test_expiry_queue_pops_in_deadline_order() {
+        let mut queue = ExpiryQueue::default();
+        queue.schedule(at("2026-03-05T00:00:00Z"), 1, 10);
+        queue.schedule(at("2026-03-02T00:00:00Z"), 2, 20);
+        queue.schedule(at("2026-03-09T00:00:00Z"), 3, 30);
+
+        assert!(queue.due(at("2026-03-01T00:00:00Z")).is_empty());
+
+        let due: Vec<u32> = queue.due(at("2026-03-06T00:00:00Z")).iter().map(|d| d.2).collect();
+        assert_eq!(due, vec![20, 10]);
+    }
+}
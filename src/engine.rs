@@ -0,0 +1,534 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::mpsc::{channel, sync_channel, Sender, SyncSender};
+use std::thread;
+use std::thread::JoinHandle;
+use crate::alerts::Alert;
+use crate::audit::ClientSnapshot;
+use crate::client::{new_clients_map, Client, GlobalClientsMap};
+use crate::config::Config;
+use crate::csv_ingestor::{read_csv, CsvTransaction, IngestionError, IngestionStats, DEFAULT_BATCH_SIZE};
+use crate::currency::{Currency, ExchangeRates};
+use crate::dispatcher::{Command, Dispatcher};
+use crate::dispute::DisputePolicy;
+use crate::error::ApplicationError;
+use crate::event_store::{EventStore, SharedEventStore};
+use crate::limits::LimitProfiles;
+use crate::parallel_ingestor::{read_csv_parallel, ParallelRead};
+use crate::risk::RiskRule;
+use crate::sequencer::OrderingPolicy;
+use crate::worker::spawn_workers;
+
+/// Commands each pipeline channel holds before senders block
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Assembles an `Engine`, every setting has a default
+pub struct EngineBuilder {
+    workers: usize,
+    channel_capacity: usize,
+    batch_size: usize,
+    config: Config,
+    exchange_rates: Option<ExchangeRates>,
+    alerts: Option<Sender<Alert>>,
+    snapshot_interval: Option<usize>,
+    recorded_clients: Option<HashSet<u16>>,
+    parallel_read: Option<ParallelRead>,
+    sources: Vec<String>,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self {
+            workers: num_cpus::get(),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            batch_size: DEFAULT_BATCH_SIZE,
+            config: Config::default(),
+            exchange_rates: None,
+            alerts: None,
+            snapshot_interval: None,
+            recorded_clients: None,
+            parallel_read: None,
+            sources: Vec::new(),
+        }
+    }
+}
+
+impl EngineBuilder {
+    /// Number of worker threads, one per CPU by default
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Capacity of the dispatcher and worker channels, submitting blocks while they are full
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity.max(1);
+        self
+    }
+
+    /// Rows a source reads before sending them on as one batch, workers apply a batch under a single lock
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Replace every runtime option at once
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn ordering(mut self, ordering: OrderingPolicy) -> Self {
+        self.config.ordering = ordering;
+        self
+    }
+
+    pub fn disputes(mut self, disputes: DisputePolicy) -> Self {
+        self.config.disputes = disputes;
+        self
+    }
+
+    pub fn default_currency(mut self, default_currency: Currency) -> Self {
+        self.config.default_currency = default_currency;
+        self
+    }
+
+    pub fn limits(mut self, limits: LimitProfiles) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    /// Add a risk rule, rules are evaluated in the order they were added
+    pub fn risk_rule(mut self, rule: Arc<dyn RiskRule>) -> Self {
+        self.config.risk_rules.push(rule);
+        self
+    }
+
+    pub fn audit(mut self, audit: bool) -> Self {
+        self.config.audit = audit;
+        self
+    }
+
+    /// Convert foreign-currency transactions into the rates' base currency before they are dispatched.
+    /// The base becomes the default currency, so converted rows and rows without a currency share one balance.
+    pub fn exchange_rates(mut self, exchange_rates: ExchangeRates) -> Self {
+        self.config.default_currency = exchange_rates.base().clone();
+        self.exchange_rates = Some(exchange_rates);
+        self
+    }
+
+    /// Where alerts go, they are dropped when no sender is given
+    pub fn alerts(mut self, alerts: Sender<Alert>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    /// Keep every applied event in an event store, snapshotting clients every `snapshot_interval` events
+    pub fn event_store(mut self, snapshot_interval: usize) -> Self {
+        self.snapshot_interval = Some(snapshot_interval);
+        self
+    }
+
+    /// Only keep the events of these clients in the event store, every client's by default
+    pub fn record_clients(mut self, clients: impl IntoIterator<Item = u16>) -> Self {
+        self.recorded_clients = Some(clients.into_iter().collect());
+        self
+    }
+
+    /// Parse each source in chunks on several threads instead of on its reading thread alone
+    pub fn parallel_read(mut self, parallel_read: ParallelRead) -> Self {
+        self.parallel_read = Some(parallel_read);
+        self
+    }
+
+    /// CSV file read on its own thread as soon as the engine starts
+    pub fn source(mut self, csv_path: impl Into<String>) -> Self {
+        self.sources.push(csv_path.into());
+        self
+    }
+
+    /// Spawn the dispatcher, the workers and one ingestion thread per source.
+    /// Panics when a default currency set after the exchange rates differs from their base.
+    pub fn build(self) -> Engine {
+        if let Some(exchange_rates) = &self.exchange_rates {
+            assert_eq!(
+                exchange_rates.base(), &self.config.default_currency,
+                "exchange rates convert into {} but rows without a currency are booked in {}", exchange_rates.base(), self.config.default_currency,
+            );
+        }
+        let config = Arc::new(self.config);
+        let clients = new_clients_map();
+        let event_store = self.snapshot_interval.map(|interval| {
+            let mut event_store = EventStore::new(interval);
+            if let Some(clients) = self.recorded_clients {
+                event_store = event_store.only_clients(clients);
+            }
+            event_store.shared()
+        });
+        let exchange_rates = self.exchange_rates.map(Arc::new);
+        // without a listener alerts go nowhere, sending them is best effort
+        let alerts = self.alerts.unwrap_or_else(|| channel().0);
+
+        let (worker_senders, worker_handles) = spawn_workers(
+            self.workers, self.channel_capacity, clients.clone(), config.clone(), alerts, event_store.clone(),
+        );
+
+        let (dispatcher_sender, ingestion_receiver) = sync_channel(self.channel_capacity);
+        let dispatcher_handle = Dispatcher::new(worker_senders).start(ingestion_receiver);
+
+        let (batch_size, parallel_read) = (self.batch_size, self.parallel_read);
+        let source_handles = self.sources.into_iter()
+            .map(|csv_path| {
+                let dispatcher_sender = dispatcher_sender.clone();
+                let exchange_rates = exchange_rates.clone();
+                thread::spawn(move || match parallel_read {
+                    Some(options) => read_csv_parallel(&csv_path, dispatcher_sender, exchange_rates.as_deref(), batch_size, options),
+                    None => read_csv(&csv_path, dispatcher_sender, exchange_rates.as_deref(), batch_size),
+                })
+            })
+            .collect();
+
+        Engine {
+            config,
+            clients,
+            event_store,
+            exchange_rates,
+            workers: self.workers,
+            dispatcher_sender,
+            dispatcher_handle,
+            worker_handles,
+            source_handles,
+        }
+    }
+}
+
+/// What the engine left behind once shut down
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    pub clients: HashMap<u16, Client>,
+    /// One per source, in the order the sources were given: its stats, or what stopped it and how far it got
+    pub ingestion: Vec<Result<IngestionStats, IngestionError>>,
+    /// Errors that stopped a pipeline thread, those of a source are in its `ingestion` entry.
+    /// Transaction errors are only logged.
+    pub errors: Vec<ApplicationError>,
+}
+
+/// A running pipeline: transactions go through the dispatcher to the worker owning their client
+pub struct Engine {
+    config: Arc<Config>,
+    clients: GlobalClientsMap,
+    event_store: Option<SharedEventStore>,
+    exchange_rates: Option<Arc<ExchangeRates>>,
+    workers: usize,
+    dispatcher_sender: SyncSender<Command>,
+    dispatcher_handle: JoinHandle<Result<(), ApplicationError>>,
+    worker_handles: Vec<JoinHandle<Result<(), ApplicationError>>>,
+    source_handles: Vec<JoinHandle<Result<IngestionStats, IngestionError>>>,
+}
+
+impl Engine {
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+
+    /// The event store, when the engine was built with one
+    pub fn event_store(&self) -> Option<&SharedEventStore> {
+        self.event_store.as_ref()
+    }
+
+    /// Queue a transaction, blocking while the pipeline is full.
+    /// It is applied asynchronously, `flush` waits for it.
+    pub fn submit(&self, mut csv_transaction: CsvTransaction) -> Result<(), ApplicationError> {
+        if let Some(exchange_rates) = &self.exchange_rates {
+            exchange_rates.convert(&mut csv_transaction)?;
+        }
+        self.dispatcher_sender
+            .send(Command::Transaction(csv_transaction))
+            .map_err(|e| ApplicationError::Other(format!("Dispatcher channel closed: {}", e)))
+    }
+
+    /// Queue transactions as one batch, each worker applies its share of it under a single lock.
+    /// Blocks while the pipeline is full, `flush` waits for the batch to be applied.
+    pub fn submit_batch(&self, mut batch: Vec<CsvTransaction>) -> Result<(), ApplicationError> {
+        if let Some(exchange_rates) = &self.exchange_rates {
+            for csv_transaction in &mut batch {
+                exchange_rates.convert(csv_transaction)?;
+            }
+        }
+        self.dispatcher_sender
+            .send(Command::Batch(batch))
+            .map_err(|e| ApplicationError::Other(format!("Dispatcher channel closed: {}", e)))
+    }
+
+    /// Wait until every transaction dispatched so far is applied, including those sequencers were holding back
+    pub fn flush(&self) -> Result<(), ApplicationError> {
+        let (ack_sender, ack_receiver) = channel();
+        self.dispatcher_sender
+            .send(Command::Flush(ack_sender))
+            .map_err(|e| ApplicationError::Other(format!("Dispatcher channel closed: {}", e)))?;
+
+        for _ in 0..self.workers {
+            ack_receiver.recv()
+                .map_err(|e| ApplicationError::Other(format!("Worker stopped before flushing: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// State and balances of every client right now, call `flush` first for everything submitted to be included
+    pub fn snapshot(&self) -> BTreeMap<u16, ClientSnapshot> {
+        self.clients.read().unwrap()
+            .iter()
+            .map(|(client_id, client)| (*client_id, ClientSnapshot::of(client)))
+            .collect()
+    }
+
+    /// Wait for the sources to be read, apply everything still queued and stop every thread
+    pub fn shutdown(self) -> ShutdownReport {
+        let mut errors = Vec::new();
+
+        // Wait on CSV threads
+        let ingestion = self.source_handles.into_iter()
+            .map(|handle| {
+                let result = handle.join().unwrap_or_else(|panic| Err(IngestionError {
+                    stats: IngestionStats::default(),
+                    error: ApplicationError::Other(format!("CSV ingestion panic: {:?}", panic)),
+                }));
+                match &result {
+                    Ok(stats) => eprintln!("CSV ingestion thread finished: {}", stats),
+                    Err(e) => eprintln!("CSV ingestion thread stopped after {}", e.stats),
+                }
+                result
+            })
+            .collect();
+
+        // Wait on dispatcher, it stops once the last sender is gone
+        drop(self.dispatcher_sender);
+        match self.dispatcher_handle.join() {
+            Ok(Ok(())) => eprintln!("Dispatcher terminated"),
+            Ok(Err(e)) => errors.push(e),
+            Err(panic) => errors.push(
+                ApplicationError::Other(format!("Dispatcher panic: {:?}", panic))
+            ),
+        }
+
+        // Wait on workers
+        for handle in self.worker_handles {
+            match handle.join() {
+                Ok(Ok(())) => eprintln!("Worker terminated"),
+                Ok(Err(e)) => errors.push(e),
+                Err(panic) => errors.push(
+                    ApplicationError::Other(format!("Worker panic: {:?}", panic))
+                ),
+            }
+        }
+
+        let clients = std::mem::take(&mut *self.clients.write().unwrap());
+        ShutdownReport { clients, ingestion, errors }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+    use rust_decimal::Decimal;
+    use crate::csv_ingestor::TransactionType;
+    use crate::lifecycle::AccountState;
+
+
+    fn available(snapshot: &BTreeMap<u16, ClientSnapshot>, client_id: u16) -> Decimal {
+        snapshot[&client_id].balances[&Currency::default()].available
+    }
+
+    #[test]
+    fn test_submit_flush_snapshot() {
+        let engine = Engine::builder().workers(2).channel_capacity(4).build();
+
+        for client_id in 1..=3 {
+            engine.submit(tx(TransactionType::Deposit, client_id, client_id as u32).with_amount(100)).unwrap();
+        }
+        engine.submit(tx(TransactionType::Withdrawal, 1, 10).with_amount(40)).unwrap();
+        engine.flush().unwrap();
+
+        let snapshot = engine.snapshot();
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(available(&snapshot, 1), Decimal::from(60));
+        assert_eq!(available(&snapshot, 3), Decimal::from(100));
+
+        let report = engine.shutdown();
+        assert!(report.errors.is_empty());
+        assert_eq!(report.clients.len(), 3);
+    }
+
+    #[test]
+    fn test_exchange_rates_base_is_the_default_currency() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let exchange_rates = ExchangeRates::load("tests/rates.csv", eur.clone()).unwrap();
+        let engine = Engine::builder().exchange_rates(exchange_rates).build();
+
+        engine.submit(tx(TransactionType::Deposit, 1, 1).with_amount(100).at("2026-03-01T12:00:00Z")).unwrap();
+        engine.submit(tx(TransactionType::Deposit, 1, 2).with_amount(10).with_currency("GBP").at("2026-03-01T12:00:00Z")).unwrap();
+        engine.flush().unwrap();
+
+        // one balance for the converted row and the one without a currency
+        let balances = &engine.snapshot()[&1].balances;
+        assert_eq!(balances.keys().collect::<Vec<_>>(), vec![&eur]);
+        assert_eq!(balances[&eur].available, Decimal::new(1117, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "exchange rates convert into EUR but rows without a currency are booked in USD")]
+    fn test_default_currency_must_match_exchange_rates() {
+        let exchange_rates = ExchangeRates::load("tests/rates.csv", "EUR".parse().unwrap()).unwrap();
+        Engine::builder().exchange_rates(exchange_rates).default_currency("USD".parse().unwrap()).build();
+    }
+
+    #[test]
+    fn test_flush_drains_sequencers() {
+        let engine = Engine::builder()
+            .workers(1)
+            .ordering(OrderingPolicy::Reorder { window: 10 })
+            .build();
+
+        engine.submit(tx(TransactionType::Withdrawal, 1, 2).with_amount(40).at("2026-03-01T09:00:00Z")).unwrap();
+        engine.submit(tx(TransactionType::Deposit, 1, 1).with_amount(100).at("2026-03-01T08:00:00Z")).unwrap();
+
+        // both events are still within the reorder window, flushing applies them in timestamp order
+        engine.flush().unwrap();
+        assert_eq!(available(&engine.snapshot(), 1), Decimal::from(60));
+        engine.shutdown();
+    }
+
+    #[test]
+    fn test_expiry_settles_the_overdue_portion() {
+        use crate::client::Balance;
+        use crate::dispute::OverdraftPolicy;
+        use crate::event_store::Cutoff;
+
+        let disputes = DisputePolicy {
+            expiry: Some("1d:resolve".parse().unwrap()),
+            overdraft: OverdraftPolicy::HoldAvailable,
+            ..DisputePolicy::default()
+        };
+        let engine = Engine::builder().workers(1).disputes(disputes).event_store(2).build();
+
+        engine.submit(tx(TransactionType::Deposit, 1, 1).with_amount(100).at("2026-03-01T08:00:00Z")).unwrap();
+        engine.submit(tx(TransactionType::Withdrawal, 1, 2).with_amount(60).at("2026-03-01T08:30:00Z")).unwrap();
+        // two disputes of 30 arriving out of timestamp order, the second only holds the 10 left available
+        engine.submit(tx(TransactionType::Dispute, 1, 1).with_amount(30).at("2026-03-02T12:00:00Z")).unwrap();
+        engine.submit(tx(TransactionType::Dispute, 1, 1).with_amount(30).at("2026-03-02T08:00:00Z")).unwrap();
+        // only the dispute raised at 08:00 is overdue by now, resolving it releases its 10
+        engine.submit(tx(TransactionType::Deposit, 1, 3).with_amount(1).at("2026-03-03T09:00:00Z")).unwrap();
+        engine.flush().unwrap();
+
+        let expected = Balance { available: Decimal::from(11), held: Decimal::from(30), total: Decimal::from(41) };
+        assert_eq!(engine.snapshot()[&1].balances[&Currency::default()], expected);
+        let replayed = engine.event_store().unwrap().read().unwrap().client_at(1, Cutoff::AfterTx(3), engine.config()).unwrap();
+        assert_eq!(replayed.balance(&Currency::default()), expected);
+
+        let report = engine.shutdown();
+        let open = &report.clients[&1].disputes[&1];
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].raised_at, tx(TransactionType::Dispute, 1, 1).at("2026-03-02T12:00:00Z").timestamp);
+    }
+
+    #[test]
+    fn test_record_clients() {
+        let engine = Engine::builder().workers(2).event_store(10).record_clients([1]).build();
+
+        for client_id in 1..=2 {
+            engine.submit(tx(TransactionType::Deposit, client_id, client_id as u32).with_amount(100)).unwrap();
+        }
+        engine.flush().unwrap();
+
+        let event_store = engine.event_store().unwrap().read().unwrap();
+        assert_eq!(event_store.events(1).len(), 1);
+        assert!(event_store.events(2).is_empty());
+        drop(event_store);
+        engine.shutdown();
+    }
+
+    #[test]
+    fn test_flags_only_for_applied_transactions() {
+        use crate::alerts::AlertKind;
+        use crate::risk::{parse_rule, RiskDecision};
+
+        // denies withdrawals of more than 1000, after the flagging rule has run
+        #[derive(Debug)]
+        struct DenyLarge;
+        impl RiskRule for DenyLarge {
+            fn name(&self) -> &str {
+                "deny-large"
+            }
+            fn evaluate(&self, _client: &Client, tx: &CsvTransaction) -> RiskDecision {
+                match (&tx.tx_type, tx.amount) {
+                    (TransactionType::Withdrawal, Some(amount)) if amount > Decimal::from(1000) => RiskDecision::Deny("too large".to_string()),
+                    _ => RiskDecision::Allow,
+                }
+            }
+        }
+
+        let (alert_sender, alert_receiver) = channel();
+        let engine = Engine::builder()
+            .workers(1)
+            .alerts(alert_sender)
+            .risk_rule(parse_rule("large-deposit-withdrawal:1000:1h").unwrap())
+            .risk_rule(Arc::new(DenyLarge))
+            .build();
+
+        engine.submit(tx(TransactionType::Deposit, 1, 1).with_amount(1500).at("2026-03-01T08:00:00Z")).unwrap();
+        // flagged, then denied by the later rule
+        engine.submit(tx(TransactionType::Withdrawal, 1, 2).with_amount(4000).at("2026-03-01T08:10:00Z")).unwrap();
+        // flagged and applied
+        engine.submit(tx(TransactionType::Withdrawal, 1, 3).with_amount(900).at("2026-03-01T08:20:00Z")).unwrap();
+        // flagged, then refused for lack of funds
+        engine.submit(tx(TransactionType::Withdrawal, 1, 4).with_amount(900).at("2026-03-01T08:30:00Z")).unwrap();
+        engine.shutdown();
+
+        let alerts: Vec<(u32, AlertKind)> = alert_receiver.try_iter().map(|alert| (alert.tx_id, alert.kind)).collect();
+        assert_eq!(alerts, vec![(2, AlertKind::Deny), (3, AlertKind::Flag)]);
+    }
+
+    #[test]
+    fn test_sources() {
+        let report = Engine::builder()
+            .source("tests/transactions.csv")
+            .build()
+            .shutdown();
+
+        assert!(report.errors.is_empty());
+        assert_eq!(report.clients[&2].state, AccountState::Locked);
+        assert_eq!(report.clients[&3].balance(&Currency::default()).available, Decimal::new(11002323, 4));
+
+        let report = Engine::builder().source("tests/malformed.csv").build().shutdown();
+        assert!(report.errors.is_empty());
+        let [Err(e)] = &report.ingestion[..] else { panic!("{:?}", report.ingestion) };
+        assert!(matches!(e.error, ApplicationError::FailedDeserializedCsvTransaction(_)));
+        // the row before the malformed one was read and applied
+        assert_eq!(e.stats.rows_read, 1);
+        assert_eq!(report.clients[&1].balance(&Currency::default()).available, Decimal::new(1002147, 4));
+
+        // one entry per source, in order, whether it was read to the end or not
+        let report = Engine::builder()
+            .source("tests/transactions.csv")
+            .source("tests/missing.csv")
+            .source("tests/malformed.csv")
+            .build()
+            .shutdown();
+        assert!(matches!(report.ingestion[..], [Ok(_), Err(IngestionError { error: ApplicationError::CouldNotOpenFile(_), .. }), Err(_)]));
+        assert_eq!(report.ingestion[2].as_ref().unwrap_err().stats.rows_read, 1);
+
+        let report = Engine::builder()
+            .parallel_read(ParallelRead { parsers: 2, chunk_size: 64, mmap: true })
+            .source("tests/transactions.csv")
+            .build()
+            .shutdown();
+        assert!(report.errors.is_empty());
+        assert_eq!(report.clients[&3].balance(&Currency::default()).available, Decimal::new(11002323, 4));
+    }
+}
@@ -1,4 +1,6 @@
 use thiserror::Error;
+use crate::currency::Currency;
+use crate::lifecycle::AccountState;
 
 #[derive(Error, Debug)]
 pub enum ApplicationError {
@@ -11,9 +13,62 @@ pub enum ApplicationError {
     #[error("Client has insufficient balance for withdrawal. More info: client-id {0}, tx-id {1}")]
     InsufficientAvailableBalanceForWithdrawal(u16, u32),
 
-    #[error("Client account is frozen, cannot perform transaction. More info: client-id {0}, tx-id {1}")]
-    ClientAccountFrozen(u16, u32),
+    #[error("Withdrawal exceeds the {2} limit. More info: client-id {0}, tx-id {1}")]
+    LimitExceeded(u16, u32, &'static str),
 
+    #[error("Transaction denied by risk rule {2}. More info: client-id {0}, tx-id {1}")]
+    RiskDenied(u16, u32, String),
+
+    #[error("Client account is {2}, cannot perform transaction. More info: client-id {0}, tx-id {1}")]
+    TransactionNotAllowed(u16, u32, AccountState),
+
+    #[error("Admin operation needs an audit reason. More info: client-id {0}, tx-id {1}")]
+    MissingAuditReason(u16, u32),
+
+    #[error("Transaction timestamp is older than the client's latest event. More info: client-id {0}, tx-id {1}")]
+    OutOfOrderTransaction(u16, u32),
+
+    #[error("Client has insufficient available balance to hold the disputed amount. More info: client-id {0}, tx-id {1}")]
+    InsufficientAvailableBalanceForDispute(u16, u32),
+
+    #[error("Referenced tx for dispute is not a known transaction with an amount. More info: client-id {0}, tx-id {1}")]
+    DisputeOfUnknownTransaction(u16, u32),
+
+    #[error("Referenced tx has no open dispute, or none for that amount. More info: client-id {0}, tx-id {1}")]
+    NotUnderDispute(u16, u32),
+
+    #[error("Dispute amount must be positive and within the undisputed part of the transaction. More info: client-id {0}, tx-id {1}")]
+    InvalidDisputeAmount(u16, u32),
+
+    #[error("Refund exceeds what is left to refund of the original deposit. More info: client-id {0}, tx-id {1}")]
+    RefundExceedsDeposit(u16, u32),
+
+    #[error("Refund amount must be positive. More info: client-id {0}, tx-id {1}")]
+    InvalidRefundAmount(u16, u32),
+
+    #[error("Referenced tx for refund is not a known deposit of the client. More info: client-id {0}, tx-id {1}")]
+    RefundOfUnknownDeposit(u16, u32),
+
+    #[error("Transaction is under dispute and cannot be refunded. More info: client-id {0}, tx-id {1}")]
+    RefundOfDisputedTransaction(u16, u32),
+
+    #[error("Client has insufficient available balance for refund. More info: client-id {0}, tx-id {1}")]
+    InsufficientAvailableBalanceForRefund(u16, u32),
+
+    #[error("Dispute raised outside the allowed window after the original transaction. More info: client-id {0}, tx-id {1}")]
+    DisputeWindowExpired(u16, u32),
+
+    #[error("Invalid currency code: {0}")]
+    InvalidCurrency(String),
+
+    #[error("No exchange rate from {2} into the base currency. More info: client-id {0}, tx-id {1}")]
+    MissingExchangeRate(u16, u32, Currency),
+
+    #[error("Amount in {2} overflows once converted into the base currency. More info: client-id {0}, tx-id {1}")]
+    ConversionOverflow(u16, u32, Currency),
+
+    #[error("Invalid configuration: {0}")]
+    InvalidConfiguration(String),
 
     #[error("Other error: {0}")]
     Other(String),
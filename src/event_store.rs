@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Utc};
+use crate::client::Client;
+use crate::config::Config;
+use crate::csv_ingestor::CsvTransaction;
+use crate::error::ApplicationError;
+use crate::worker::replay;
+
+/// Client state is snapshotted after this many events by default
+pub const DEFAULT_SNAPSHOT_INTERVAL: usize = 1000;
+
+/// Snapshots kept per client. Every snapshot holds the client's whole history, so past this many
+/// every other one is dropped and the client's interval doubles, keeping memory linear in its events.
+pub const MAX_SNAPSHOTS: usize = 16;
+
+/// Event store shared by the workers, each appends the events of its own clients
+pub type SharedEventStore = Arc<RwLock<EventStore>>;
+
+/// Why an event was applied to a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSource {
+    /// A row of the input, after sequencing
+    Input,
+    /// A resolve or chargeback the worker applied because the dispute was open too long,
+    /// `raised_at` tells which open portion of the tx it settled
+    DisputeExpiry { raised_at: DateTime<Utc> },
+    /// A row of the input the sequencer refused, it never reached the client
+    Refused,
+}
+
+/// One event in the order it was applied to its client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredEvent {
+    pub tx: CsvTransaction,
+    pub source: EventSource,
+    /// Why the event was rejected, the client is left as it was
+    pub rejection: Option<String>,
+}
+
+/// Where a point-in-time query stops replaying
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cutoff {
+    /// Up to and including the first event carrying this tx id
+    AfterTx(u32),
+    /// Up to the first event timestamped later than this
+    At(DateTime<Utc>),
+}
+
+impl FromStr for Cutoff {
+    type Err = ApplicationError;
+
+    /// Accepts a tx id or an RFC3339 timestamp
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(tx_id) = s.parse() {
+            return Ok(Cutoff::AfterTx(tx_id));
+        }
+        DateTime::parse_from_rfc3339(s)
+            .map(|at| Cutoff::At(at.with_timezone(&Utc)))
+            .map_err(|_| ApplicationError::InvalidConfiguration(
+                format!("invalid cutoff '{}', expected a tx id or an RFC3339 timestamp", s)
+            ))
+    }
+}
+
+impl fmt::Display for Cutoff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cutoff::AfterTx(tx_id) => write!(f, "after tx {}", tx_id),
+            Cutoff::At(at) => write!(f, "at {}", at.to_rfc3339()),
+        }
+    }
+}
+
+/// Events of one client plus snapshots of its state taken along the way
+#[derive(Debug)]
+struct ClientLog {
+    events: Vec<StoredEvent>,
+    // client state after the first `n` events, oldest first, `n` is a multiple of `snapshot_interval`
+    snapshots: Vec<(usize, Client)>,
+    snapshot_interval: usize,
+}
+
+/// Every event applied to each client, kept so client state can be rebuilt as of any point in the stream
+#[derive(Debug)]
+pub struct EventStore {
+    snapshot_interval: usize,
+    // clients whose events are kept, every client's when None
+    clients: Option<HashSet<u16>>,
+    logs: HashMap<u16, ClientLog>,
+}
+
+impl Default for EventStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_SNAPSHOT_INTERVAL)
+    }
+}
+
+impl EventStore {
+    pub fn new(snapshot_interval: usize) -> Self {
+        Self { snapshot_interval: snapshot_interval.max(1), clients: None, logs: HashMap::new() }
+    }
+
+    /// Only keep the events of `clients`, the others are dropped on append
+    pub fn only_clients(mut self, clients: impl IntoIterator<Item = u16>) -> Self {
+        self.clients = Some(clients.into_iter().collect());
+        self
+    }
+
+    pub fn shared(self) -> SharedEventStore {
+        Arc::new(RwLock::new(self))
+    }
+
+    /// Append an event once it has been applied to `client`, snapshotting the client every `snapshot_interval` events,
+    /// less often once it has `MAX_SNAPSHOTS` of them
+    pub fn append(&mut self, client: &Client, tx: &CsvTransaction, source: EventSource, rejection: Option<String>) {
+        if self.clients.as_ref().is_some_and(|clients| !clients.contains(&tx.client_id)) {
+            return;
+        }
+        let log = self.logs.entry(tx.client_id).or_insert_with(|| ClientLog {
+            events: Vec::new(),
+            snapshots: Vec::new(),
+            snapshot_interval: self.snapshot_interval,
+        });
+        log.events.push(StoredEvent { tx: tx.clone(), source, rejection });
+
+        let applied = log.events.len();
+        if !applied.is_multiple_of(log.snapshot_interval) {
+            return;
+        }
+        if log.snapshots.len() == MAX_SNAPSHOTS {
+            let interval = log.snapshot_interval * 2;
+            log.snapshots.retain(|(applied, _)| applied.is_multiple_of(interval));
+            log.snapshot_interval = interval;
+        }
+        if applied.is_multiple_of(log.snapshot_interval) {
+            log.snapshots.push((applied, client.clone()));
+        }
+    }
+
+    /// Events applied to a client, oldest first
+    pub fn events(&self, client_id: u16) -> &[StoredEvent] {
+        self.logs.get(&client_id).map_or(&[], |log| &log.events)
+    }
+
+    /// Number of events up to `cutoff`, None when the tx id of the cutoff never reached the client
+    fn cutoff_len(events: &[StoredEvent], cutoff: Cutoff) -> Option<usize> {
+        match cutoff {
+            Cutoff::AfterTx(tx_id) => events.iter()
+                .position(|event| event.tx.tx_id == tx_id)
+                .map(|index| index + 1),
+            Cutoff::At(at) => Some(events.iter()
+                .position(|event| event.tx.timestamp.is_some_and(|timestamp| timestamp > at))
+                .unwrap_or(events.len())),
+        }
+    }
+
+    /// Rebuild a client as it was at `cutoff` by replaying its events from the closest earlier snapshot.
+    /// `config` must be the one the events were processed with.
+    /// None for a client that was never seen, or a tx id the client never saw.
+    pub fn client_at(&self, client_id: u16, cutoff: Cutoff, config: &Arc<Config>) -> Option<Client> {
+        let log = self.logs.get(&client_id)?;
+        let len = Self::cutoff_len(&log.events, cutoff)?;
+
+        let (start, client) = log.snapshots.iter()
+            .rev()
+            .find(|(applied, _)| *applied <= len)
+            .map_or_else(|| (0, Client::default()), |(applied, client)| (*applied, client.clone()));
+
+        Some(replay(config, client_id, client, &log.events[start..len]))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+    use rust_decimal::Decimal;
+    use crate::csv_ingestor::TransactionType;
+
+
+    #[test]
+    fn test_parse_cutoff() {
+        assert_eq!("123456".parse::<Cutoff>().unwrap(), Cutoff::AfterTx(123456));
+        assert!(matches!("2026-03-01T00:00:00Z".parse::<Cutoff>().unwrap(), Cutoff::At(_)));
+        assert!("yesterday".parse::<Cutoff>().is_err());
+    }
+
+    #[test]
+    fn test_client_at() {
+        let config = Arc::new(Config::default());
+        let events = vec![
+            tx(TransactionType::Deposit, 7, 1).with_amount(100).at("2026-02-27T10:00:00Z"),
+            tx(TransactionType::Withdrawal, 7, 2).with_amount(30).at("2026-02-28T10:00:00Z"),
+            tx(TransactionType::Dispute, 7, 1).at("2026-03-01T10:00:00Z"),
+            tx(TransactionType::Deposit, 7, 3).with_amount(5).at("2026-03-02T10:00:00Z"),
+            tx(TransactionType::Resolve, 7, 1).at("2026-03-03T10:00:00Z"),
+        ];
+
+        // snapshot every other event, so queries start both from snapshots and from scratch
+        let mut store = EventStore::new(2);
+        let mut live = Client::default();
+        for tx in &events {
+            live = replay(&config, 7, live, &[StoredEvent { tx: tx.clone(), source: EventSource::Input, rejection: None }]);
+            store.append(&live, tx, EventSource::Input, None);
+        }
+
+        let available = |cutoff: &str| {
+            let client = store.client_at(7, cutoff.parse().unwrap(), &config).unwrap();
+            client.balance(&Default::default()).available
+        };
+        assert_eq!(available("2"), Decimal::from(70));
+        // the first event with the tx id is the deposit itself
+        assert_eq!(available("1"), Decimal::from(100));
+        assert_eq!(available("3"), Decimal::from(-25));
+        assert_eq!(available("2026-03-01T00:00:00Z"), Decimal::from(70));
+        assert_eq!(available("2026-03-02T12:00:00Z"), Decimal::from(-25));
+        assert_eq!(available("2026-12-31T00:00:00Z"), live.balance(&Default::default()).available);
+
+        assert!(store.client_at(8, Cutoff::AfterTx(1), &config).is_none());
+        assert!(store.client_at(7, Cutoff::AfterTx(4), &config).is_none());
+    }
+
+    #[test]
+    fn test_snapshots_are_thinned_out() {
+        let config = Arc::new(Config::default());
+        let mut store = EventStore::new(1);
+        let mut live = Client::default();
+        for tx_id in 1..=100 {
+            let tx = tx(TransactionType::Deposit, 7, tx_id).with_amount(1).at("2026-03-01T10:00:00Z");
+            live.deposit(&tx).unwrap();
+            store.append(&live, &tx, EventSource::Input, None);
+        }
+
+        // 16 snapshots at every event, thinned to every other, then every fourth and so on
+        let log = &store.logs[&7];
+        assert_eq!(log.snapshot_interval, 8);
+        let applied: Vec<usize> = log.snapshots.iter().map(|(applied, _)| *applied).collect();
+        assert_eq!(applied, (1..=12).map(|n| n * 8).collect::<Vec<_>>());
+
+        for tx_id in [1, 8, 50, 96, 100] {
+            let client = store.client_at(7, Cutoff::AfterTx(tx_id), &config).unwrap();
+            assert_eq!(client.balance(&Default::default()).total, Decimal::from(tx_id));
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+use chrono::{DateTime, TimeDelta, Utc};
+use rust_decimal::Decimal;
+use crate::client::write_balances;
+use crate::config::Config;
+use crate::csv_ingestor::{CsvTransaction, TransactionType};
+use crate::currency::Currency;
+use crate::error::ApplicationError;
+use crate::reference::ReferenceEngine;
+
+// deposits remembered per client for disputes to reference, older ones are forgotten
+const REMEMBERED_DEPOSITS: usize = 16;
+// open disputes beyond this are settled right away, keeping memory flat for any output size
+const MAX_OPEN_DISPUTES: usize = 1024;
+// share of rows that are withdrawals, the rest of the non-dispute rows are deposits
+const WITHDRAWAL_RATE: f64 = 0.3;
+
+/// xorshift64, deterministic for a seed and enough for synthetic data without a dependency
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // the state must never be zero
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Uniform in `[0, 1)`
+    pub(crate) fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Layout of the generated file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// `type,client,tx,amount,timestamp` with a header, what drizzly reads
+    #[default]
+    Csv,
+    /// One JSON object per line with the same fields
+    Jsonl,
+}
+
+impl FromStr for OutputFormat {
+    type Err = ApplicationError;
+
+    /// Accepts `csv` or `jsonl`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            _ => Err(ApplicationError::InvalidConfiguration(
+                format!("unknown output format '{}', expected csv or jsonl", s)
+            )),
+        }
+    }
+}
+
+/// Parse a size in bytes, optionally suffixed with `K`, `M` or `G` (powers of 1024), e.g. `512M`
+pub fn parse_size(s: &str) -> Result<u64, ApplicationError> {
+    let invalid = || ApplicationError::InvalidConfiguration(
+        format!("invalid size '{}', expected bytes or e.g. 64K, 512M, 2G", s)
+    );
+
+    let (value, multiplier) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    value.parse::<u64>().ok()
+        .and_then(|value| value.checked_mul(multiplier))
+        .ok_or_else(invalid)
+}
+
+/// What to generate, every setting has a default
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    /// Client ids run from 1 to this
+    pub clients: u16,
+    /// Zipf exponent of client activity: 0 spreads rows evenly, higher values concentrate them on low client ids
+    pub skew: f64,
+    /// Share of rows disputing an earlier deposit, as many rows again settle open disputes
+    pub dispute_rate: f64,
+    /// Share of settled disputes that end in a chargeback instead of a resolve
+    pub chargeback_rate: f64,
+    /// Share of rows written malformed
+    pub malformed_rate: f64,
+    pub seed: u64,
+    /// Time of the first row, each following row is up to two seconds later
+    pub start: DateTime<Utc>,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            clients: 1000,
+            skew: 1.0,
+            dispute_rate: 0.01,
+            chargeback_rate: 0.2,
+            malformed_rate: 0.0,
+            seed: 0,
+            start: DateTime::from_timestamp(1_767_225_600, 0).unwrap_or_default(), // 2026-01-01
+        }
+    }
+}
+
+/// One generated row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeneratedRow {
+    Valid(CsvTransaction),
+    /// A line drizzly fails to deserialize
+    Malformed(String),
+}
+
+impl GeneratedRow {
+    /// The row as a line of `format`, without the line break
+    pub fn format(&self, format: OutputFormat) -> String {
+        let tx = match self {
+            GeneratedRow::Malformed(line) => return line.clone(),
+            GeneratedRow::Valid(tx) => tx,
+        };
+        let timestamp = tx.timestamp.map_or_else(String::new, |t| t.to_rfc3339());
+
+        match format {
+            OutputFormat::Csv => format!(
+                "{},{},{},{},{}",
+                tx.tx_type, tx.client_id, tx.tx_id, tx.amount.map_or_else(String::new, |a| a.to_string()), timestamp
+            ),
+            OutputFormat::Jsonl => format!(
+                r#"{{"type":"{}","client":{},"tx":{},"amount":{},"timestamp":"{}"}}"#,
+                tx.tx_type, tx.client_id, tx.tx_id, tx.amount.map_or_else(|| "null".to_string(), |a| format!("\"{}\"", a)), timestamp
+            ),
+        }
+    }
+}
+
+/// Endless, deterministic stream of rows shaped by a `GeneratorConfig`
+pub struct Generator {
+    config: GeneratorConfig,
+    format: OutputFormat,
+    rng: Rng,
+    // cumulative client weights, client id is the index plus one
+    weights: Vec<f64>,
+    next_tx_id: u32,
+    timestamp: DateTime<Utc>,
+    deposits: HashMap<u16, VecDeque<u32>>,
+    open_disputes: VecDeque<(u16, u32)>,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig, format: OutputFormat) -> Self {
+        let mut total = 0.0;
+        let weights = (1..=config.clients.max(1))
+            .map(|rank| {
+                total += 1.0 / f64::from(rank).powf(config.skew);
+                total
+            })
+            .collect();
+
+        Self {
+            rng: Rng::new(config.seed),
+            timestamp: config.start,
+            config,
+            format,
+            weights,
+            next_tx_id: 1,
+            deposits: HashMap::new(),
+            open_disputes: VecDeque::new(),
+        }
+    }
+
+    fn pick_client(&mut self) -> u16 {
+        let total = self.weights.last().copied().unwrap_or_default();
+        let target = self.rng.unit() * total;
+        let index = self.weights.partition_point(|weight| *weight <= target);
+        index.min(self.weights.len() - 1) as u16 + 1
+    }
+
+    // 0.0001 to `max` with 4 decimal places
+    fn amount(&mut self, max: u64) -> Decimal {
+        Decimal::new(self.rng.below(max * 10_000) as i64 + 1, 4)
+    }
+
+    fn transaction(&self, tx_type: TransactionType, client_id: u16, tx_id: u32, amount: Option<Decimal>) -> CsvTransaction {
+        CsvTransaction {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            timestamp: Some(self.timestamp),
+            currency: None,
+            reason: None,
+            conversion: None,
+        }
+    }
+
+    fn malformed(&mut self, client_id: u16, tx_id: u32) -> String {
+        let amount = self.amount(1000);
+        match (self.format, self.rng.below(4)) {
+            (OutputFormat::Csv, 0) => format!("depost,{},{},{},{}", client_id, tx_id, amount, self.timestamp.to_rfc3339()),
+            (OutputFormat::Csv, 1) => format!("withdrawal {},{},{}", client_id, tx_id, amount),
+            (OutputFormat::Csv, 2) => format!("deposit,{},{},{}.5,{}", client_id, tx_id, amount, self.timestamp.to_rfc3339()),
+            (OutputFormat::Csv, _) => format!("deposit,{},{},{},{}", u32::from(u16::MAX) + 1, tx_id, amount, self.timestamp.to_rfc3339()),
+            (OutputFormat::Jsonl, 0) => format!(r#"{{"type":"depost","client":{},"tx":{},"amount":"{}"}}"#, client_id, tx_id, amount),
+            (OutputFormat::Jsonl, 1) => format!(r#"{{"type":"withdrawal","client":{},"tx":"#, client_id),
+            (OutputFormat::Jsonl, 2) => format!(r#"{{"type":"deposit","client":{},"tx":{},"amount":"{}.5"}}"#, client_id, tx_id, amount),
+            (OutputFormat::Jsonl, _) => format!(r#"{{"type":"deposit","client":{},"tx":{},"amount":"{}"}}"#, u32::from(u16::MAX) + 1, tx_id, amount),
+        }
+    }
+
+    // settle the oldest open dispute
+    fn settle(&mut self) -> Option<CsvTransaction> {
+        let (client_id, tx_id) = self.open_disputes.pop_front()?;
+        let tx_type = if self.rng.unit() < self.config.chargeback_rate { TransactionType::Chargeback } else { TransactionType::Resolve };
+        Some(self.transaction(tx_type, client_id, tx_id, None))
+    }
+}
+
+impl Iterator for Generator {
+    type Item = GeneratedRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tx_id = self.next_tx_id;
+        self.next_tx_id = tx_id.checked_add(1)?;
+        self.timestamp += TimeDelta::milliseconds(self.rng.below(2000) as i64);
+        let client_id = self.pick_client();
+
+        if self.rng.unit() < self.config.malformed_rate {
+            return Some(GeneratedRow::Malformed(self.malformed(client_id, tx_id)));
+        }
+        if self.open_disputes.len() >= MAX_OPEN_DISPUTES
+            && let Some(settlement) = self.settle()
+        {
+            return Some(GeneratedRow::Valid(settlement));
+        }
+
+        let roll = self.rng.unit();
+        let dispute_rate = self.config.dispute_rate;
+        let earlier = self.deposits.get(&client_id)
+            .filter(|deposits| !deposits.is_empty())
+            .map(|deposits| deposits[self.rng.below(deposits.len() as u64) as usize]);
+
+        let tx = match earlier {
+            Some(earlier) if roll < dispute_rate => {
+                self.open_disputes.push_back((client_id, earlier));
+                self.transaction(TransactionType::Dispute, client_id, earlier, None)
+            }
+            _ if roll < 2.0 * dispute_rate && !self.open_disputes.is_empty() => self.settle()?,
+            _ if roll < 2.0 * dispute_rate + WITHDRAWAL_RATE => {
+                let amount = self.amount(500);
+                self.transaction(TransactionType::Withdrawal, client_id, tx_id, Some(amount))
+            }
+            _ => {
+                let deposits = self.deposits.entry(client_id).or_default();
+                if deposits.len() == REMEMBERED_DEPOSITS {
+                    deposits.pop_front();
+                }
+                deposits.push_back(tx_id);
+                let amount = self.amount(1000);
+                self.transaction(TransactionType::Deposit, client_id, tx_id, Some(amount))
+            }
+        };
+        Some(GeneratedRow::Valid(tx))
+    }
+}
+
+/// What `generate` wrote
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GenerationStats {
+    pub rows: u64,
+    pub malformed: u64,
+    pub bytes: u64,
+}
+
+/// Most rows expected balances are computed for, the reference engine keeps every client's history in memory
+pub const MAX_EXPECTED_ROWS: u64 = 1_000_000;
+
+/// Expected balances are only written for CSV, the one format drizzly reads,
+/// and for at most `MAX_EXPECTED_ROWS` rows, which `max_rows` must guarantee.
+pub fn check_expected(format: OutputFormat, max_rows: Option<u64>) -> Result<(), ApplicationError> {
+    if format != OutputFormat::Csv {
+        return Err(ApplicationError::InvalidConfiguration("expected balances are only written for CSV, drizzly does not read other formats".to_string()));
+    }
+    if max_rows.is_none_or(|rows| rows > MAX_EXPECTED_ROWS) {
+        return Err(ApplicationError::InvalidConfiguration(format!(
+            "expected balances keep every client's history in memory, they need a row count of at most {}", MAX_EXPECTED_ROWS
+        )));
+    }
+    Ok(())
+}
+
+/// Write rows until at least `target_bytes` or `max_rows` is reached, whichever comes first.
+/// With `expected`, the final balances drizzly should print for the file are written to it, in drizzly's
+/// output format, see `check_expected` for when they can be. Reading stops at the first malformed row,
+/// so only rows before it count towards them. Computing them keeps every valid row in memory,
+/// the rows themselves are streamed.
+pub fn generate(
+    config: GeneratorConfig,
+    format: OutputFormat,
+    target_bytes: Option<u64>,
+    max_rows: Option<u64>,
+    mut writer: impl Write,
+    expected: Option<&mut dyn Write>,
+) -> Result<GenerationStats, ApplicationError> {
+    if expected.is_some() {
+        check_expected(format, max_rows)?;
+    }
+    let io_error = |e: std::io::Error| ApplicationError::Other(format!("Could not write generated rows: {}", e));
+    let mut stats = GenerationStats::default();
+    let mut expectation = expected.map(|expected| (ReferenceEngine::new(Arc::new(Config::default())), expected));
+
+    if format == OutputFormat::Csv {
+        let header = "type,client,tx,amount,timestamp\n";
+        writer.write_all(header.as_bytes()).map_err(io_error)?;
+        stats.bytes += header.len() as u64;
+    }
+
+    for row in Generator::new(config, format) {
+        if target_bytes.is_some_and(|target| stats.bytes >= target) || max_rows.is_some_and(|max| stats.rows >= max) {
+            break;
+        }
+
+        let line = row.format(format);
+        writer.write_all(line.as_bytes()).and_then(|_| writer.write_all(b"\n")).map_err(io_error)?;
+        stats.rows += 1;
+        stats.bytes += line.len() as u64 + 1;
+
+        match row {
+            GeneratedRow::Valid(tx) => if let Some((reference, _)) = expectation.as_mut() {
+                reference.apply(tx);
+            },
+            GeneratedRow::Malformed(_) => {
+                stats.malformed += 1;
+                // drizzly stops reading at the first malformed row
+                if let Some((reference, expected)) = expectation.take() {
+                    write_balances(&reference.finish(), &Currency::default(), expected)?;
+                }
+            }
+        }
+    }
+    writer.flush().map_err(io_error)?;
+
+    if let Some((reference, expected)) = expectation {
+        write_balances(&reference.finish(), &Currency::default(), expected)?;
+    }
+    Ok(stats)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use crate::engine::Engine;
+
+    fn rows(config: GeneratorConfig, count: usize) -> Vec<GeneratedRow> {
+        Generator::new(config, OutputFormat::Csv).take(count).collect()
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1000").unwrap(), 1000);
+        assert_eq!(parse_size("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_size("2G").unwrap(), 2 << 30);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("1.5M").is_err());
+    }
+
+    #[test]
+    fn test_generator_shape() {
+        let config = GeneratorConfig { clients: 50, skew: 1.5, malformed_rate: 0.05, seed: 7, ..GeneratorConfig::default() };
+        let generated = rows(config.clone(), 5000);
+        assert_eq!(generated, rows(config.clone(), 5000), "same seed, same rows");
+        assert_ne!(generated, rows(GeneratorConfig { seed: 8, ..config }, 5000));
+
+        let valid: Vec<&CsvTransaction> = generated.iter()
+            .filter_map(|row| match row { GeneratedRow::Valid(tx) => Some(tx), _ => None })
+            .collect();
+        let malformed = generated.len() - valid.len();
+        assert!((150..350).contains(&malformed), "{} malformed rows", malformed);
+
+        // the hottest client sees far more rows than the coldest
+        let count = |client_id| valid.iter().filter(|tx| tx.client_id == client_id).count();
+        assert!(count(1) > 10 * count(50).max(1));
+        assert!(valid.iter().all(|tx| (1..=50).contains(&tx.client_id)));
+
+        let disputes = valid.iter().filter(|tx| tx.tx_type == TransactionType::Dispute).count();
+        assert!((10..100).contains(&disputes), "{} disputes", disputes);
+        assert!(valid.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+    }
+
+    #[test]
+    fn test_expected_balances_need_csv_and_a_bounded_row_count() {
+        let expect = |format, max_rows| generate(GeneratorConfig::default(), format, Some(1000), max_rows, std::io::sink(), Some(&mut Vec::new()));
+
+        assert!(matches!(expect(OutputFormat::Jsonl, Some(10)), Err(ApplicationError::InvalidConfiguration(_))));
+        assert!(matches!(expect(OutputFormat::Csv, None), Err(ApplicationError::InvalidConfiguration(_))));
+        assert!(matches!(expect(OutputFormat::Csv, Some(MAX_EXPECTED_ROWS + 1)), Err(ApplicationError::InvalidConfiguration(_))));
+        assert_eq!(expect(OutputFormat::Csv, Some(10)).unwrap().rows, 10);
+    }
+
+    #[test]
+    fn test_expected_balances_match_pipeline() {
+        for (name, malformed_rate) in [("clean", 0.0), ("malformed", 0.002)] {
+            let path = std::env::temp_dir().join(format!("drizzly-gen-{}-{}.csv", std::process::id(), name));
+            let config = GeneratorConfig { clients: 40, dispute_rate: 0.05, chargeback_rate: 0.3, malformed_rate, seed: 3, ..GeneratorConfig::default() };
+
+            let mut expected = Vec::new();
+            let stats = generate(config, OutputFormat::Csv, None, Some(3000), File::create(&path).unwrap(), Some(&mut expected)).unwrap();
+            assert_eq!(stats.rows, 3000);
+            assert_eq!(stats.malformed > 0, malformed_rate > 0.0);
+            assert_eq!(stats.bytes, std::fs::metadata(&path).unwrap().len());
+
+            let report = Engine::builder().workers(3).source(path.to_str().unwrap()).build().shutdown();
+            std::fs::remove_file(&path).unwrap();
+            assert!(report.errors.is_empty(), "{}", name);
+            assert_eq!(report.ingestion[0].is_err(), stats.malformed > 0, "{}", name);
+
+            let mut actual = Vec::new();
+            write_balances(&report.clients, &Currency::default(), &mut actual).unwrap();
+            assert_eq!(String::from_utf8(actual).unwrap(), String::from_utf8(expected).unwrap(), "{}", name);
+        }
+    }
+}
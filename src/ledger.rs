@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use rust_decimal::Decimal;
+use crate::currency::Currency;
+use crate::error::ApplicationError;
+
+/// Named accounts money moves between
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LedgerAccount {
+    ClientAvailable,
+    ClientHeld,
+    /// Outside world: deposits come from here, withdrawals go back to it
+    Settlement,
+    /// Funds reversed by chargebacks
+    ChargebackLoss,
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LedgerAccount::ClientAvailable => "client_available",
+            LedgerAccount::ClientHeld => "client_held",
+            LedgerAccount::Settlement => "settlement",
+            LedgerAccount::ChargebackLoss => "chargeback_loss",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Moves `amount` out of `from` into `to`, so every posting is balanced on its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Posting {
+    pub tx_id: u32,
+    pub from: LedgerAccount,
+    pub to: LedgerAccount,
+    pub currency: Currency,
+    pub amount: Decimal,
+}
+
+/// Postings of one client plus running account balances derived from them
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    postings: Vec<Posting>,
+    balances: BTreeMap<(LedgerAccount, Currency), Decimal>,
+}
+
+impl Ledger {
+    pub fn post(&mut self, tx_id: u32, from: LedgerAccount, to: LedgerAccount, currency: Currency, amount: Decimal) {
+        *self.balances.entry((from, currency.clone())).or_default() -= amount;
+        *self.balances.entry((to, currency.clone())).or_default() += amount;
+        self.postings.push(Posting { tx_id, from, to, currency, amount });
+    }
+
+    /// Net of everything moved into `account` minus everything moved out, in `currency`
+    pub fn balance(&self, account: LedgerAccount, currency: &Currency) -> Decimal {
+        self.balances.get(&(account, currency.clone())).copied().unwrap_or_default()
+    }
+
+    pub fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    /// Every account and currency touched so far with its balance
+    pub fn accounts(&self) -> impl Iterator<Item = (&(LedgerAccount, Currency), &Decimal)> {
+        self.balances.iter()
+    }
+}
+
+/// Account balances summed over many ledgers.
+/// Every posting is balanced on its own, so each currency nets to zero by construction and is not reported.
+#[derive(Debug, Default)]
+pub struct TrialBalance {
+    rows: BTreeMap<(LedgerAccount, Currency), Decimal>,
+}
+
+impl TrialBalance {
+    pub fn add(&mut self, ledger: &Ledger) {
+        for (key, amount) in ledger.accounts() {
+            *self.rows.entry(key.clone()).or_default() += amount;
+        }
+    }
+
+    /// Write `account,currency,balance` rows
+    pub fn write(&self, mut writer: impl Write) -> Result<(), ApplicationError> {
+        let io_error = |e: std::io::Error| ApplicationError::Other(format!("Could not write trial balance: {}", e));
+
+        writeln!(writer, "account,currency,balance").map_err(io_error)?;
+        for ((account, currency), amount) in &self.rows {
+            writeln!(writer, "{},{},{:.4}", account, currency, amount).map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_postings_drive_balances() {
+        let usd = Currency::default();
+        let mut ledger = Ledger::default();
+
+        ledger.post(1, LedgerAccount::Settlement, LedgerAccount::ClientAvailable, usd.clone(), Decimal::from(100));
+        ledger.post(2, LedgerAccount::ClientAvailable, LedgerAccount::ClientHeld, usd.clone(), Decimal::from(30));
+
+        assert_eq!(ledger.balance(LedgerAccount::ClientAvailable, &usd), Decimal::from(70));
+        assert_eq!(ledger.balance(LedgerAccount::ClientHeld, &usd), Decimal::from(30));
+        assert_eq!(ledger.balance(LedgerAccount::Settlement, &usd), Decimal::from(-100));
+        assert_eq!(ledger.postings().len(), 2);
+    }
+
+    #[test]
+    fn test_trial_balance() {
+        let usd = Currency::default();
+        let eur: Currency = "EUR".parse().unwrap();
+
+        let mut first = Ledger::default();
+        first.post(1, LedgerAccount::Settlement, LedgerAccount::ClientAvailable, usd.clone(), Decimal::from(100));
+        let mut second = Ledger::default();
+        second.post(2, LedgerAccount::Settlement, LedgerAccount::ClientAvailable, eur.clone(), Decimal::from(5));
+        second.post(2, LedgerAccount::ClientAvailable, LedgerAccount::ChargebackLoss, eur, Decimal::from(5));
+
+        let mut trial_balance = TrialBalance::default();
+        trial_balance.add(&first);
+        trial_balance.add(&second);
+        let mut output = Vec::new();
+        trial_balance.write(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "account,currency,balance\n\
+            client_available,EUR,0.0000\n\
+            client_available,USD,100.0000\n\
+            settlement,EUR,-5.0000\n\
+            settlement,USD,-100.0000\n\
+            chargeback_loss,EUR,5.0000\n");
+    }
+}
@@ -1,8 +1,24 @@
-
-
 pub mod csv_ingestor;
+pub mod parallel_ingestor;
 pub mod error;
 pub mod client;
 pub mod worker;
 pub mod dispatcher;
+pub mod config;
+pub mod sequencer;
+pub mod dispute;
+pub mod lifecycle;
+pub mod currency;
+pub mod limits;
+pub mod alerts;
+pub mod risk;
+pub mod ledger;
+pub mod audit;
+pub mod event_store;
+pub mod statement;
+pub mod engine;
+pub mod reference;
+pub mod generator;
 
+#[cfg(test)]
+mod test_support;
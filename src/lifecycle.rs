@@ -0,0 +1,104 @@
+use std::fmt;
+use chrono::{DateTime, Utc};
+use crate::csv_ingestor::TransactionType;
+
+/// Lifecycle of a client account, replaces the former `locked` flag
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccountState {
+    #[default]
+    Active,
+    /// Set by an admin, money can come in but not go out
+    Frozen,
+    /// Set by a chargeback, nothing moves until an admin unlocks the account
+    Locked,
+    /// Set by an admin, nothing moves until the account is reopened
+    Closed,
+    /// Overdrawn by a dispute, money can come in but not go out until available is back above zero
+    Collections,
+}
+
+impl AccountState {
+    /// Whether a client-initiated transaction type may be applied in this state
+    pub fn allows(&self, tx_type: &TransactionType) -> bool {
+        match self {
+            AccountState::Active => !tx_type.is_admin(),
+            AccountState::Frozen | AccountState::Collections => matches!(
+                tx_type,
+                TransactionType::Deposit | TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback
+            ),
+            AccountState::Locked | AccountState::Closed => false,
+        }
+    }
+
+    /// State reached by applying an admin operation, None when the operation is not allowed from this state
+    pub fn transition(&self, tx_type: &TransactionType) -> Option<AccountState> {
+        match (self, tx_type) {
+            (AccountState::Active, TransactionType::Freeze) => Some(AccountState::Frozen),
+            (AccountState::Frozen | AccountState::Locked | AccountState::Collections, TransactionType::Unlock) => Some(AccountState::Active),
+            (AccountState::Active | AccountState::Frozen | AccountState::Locked | AccountState::Collections, TransactionType::Close) => Some(AccountState::Closed),
+            (AccountState::Closed, TransactionType::Reopen) => Some(AccountState::Active),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AccountState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AccountState::Active => "active",
+            AccountState::Frozen => "frozen",
+            AccountState::Locked => "locked",
+            AccountState::Closed => "closed",
+            AccountState::Collections => "collections",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A recorded change of account state and why it happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub tx_id: u32,
+    pub from: AccountState,
+    pub to: AccountState,
+    pub reason: String,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frozen_allows_deposits_only_inbound() {
+        let frozen = AccountState::Frozen;
+        assert!(frozen.allows(&TransactionType::Deposit));
+        assert!(frozen.allows(&TransactionType::Dispute));
+        assert!(!frozen.allows(&TransactionType::Withdrawal));
+        assert!(!frozen.allows(&TransactionType::Refund));
+        assert!(!frozen.allows(&TransactionType::Freeze));
+    }
+
+    #[test]
+    fn test_locked_and_closed_allow_nothing() {
+        for state in [AccountState::Locked, AccountState::Closed] {
+            assert!(!state.allows(&TransactionType::Deposit));
+            assert!(!state.allows(&TransactionType::Withdrawal));
+            assert!(!state.allows(&TransactionType::Resolve));
+        }
+    }
+
+    #[test]
+    fn test_transitions() {
+        assert_eq!(AccountState::Active.transition(&TransactionType::Freeze), Some(AccountState::Frozen));
+        assert_eq!(AccountState::Locked.transition(&TransactionType::Unlock), Some(AccountState::Active));
+        assert_eq!(AccountState::Frozen.transition(&TransactionType::Unlock), Some(AccountState::Active));
+        assert_eq!(AccountState::Locked.transition(&TransactionType::Close), Some(AccountState::Closed));
+        assert_eq!(AccountState::Closed.transition(&TransactionType::Reopen), Some(AccountState::Active));
+
+        assert_eq!(AccountState::Active.transition(&TransactionType::Unlock), None);
+        assert_eq!(AccountState::Closed.transition(&TransactionType::Freeze), None);
+        assert_eq!(AccountState::Active.transition(&TransactionType::Deposit), None);
+    }
+}
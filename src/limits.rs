@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::str::FromStr;
+use chrono::TimeDelta;
+use csv::ReaderBuilder;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use crate::config::parse_duration;
+use crate::error::ApplicationError;
+
+pub const MAX_SINGLE_WITHDRAWAL: &str = "max_single_withdrawal";
+pub const DAILY_WITHDRAWAL_TOTAL: &str = "daily_withdrawal_total";
+pub const WITHDRAWAL_VELOCITY: &str = "withdrawal_velocity";
+
+/// Withdrawal rules for one client, unset fields are unlimited.
+/// Amounts apply per currency, the daily total and velocity need timestamped rows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WithdrawalLimits {
+    pub max_single: Option<Decimal>,
+    /// Cumulative withdrawals per UTC calendar day
+    pub daily_total: Option<Decimal>,
+    /// At most `max_count` withdrawals within any `window`
+    pub velocity: Option<(usize, TimeDelta)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LimitsRow {
+    client: String,
+    max_withdrawal: Option<String>,
+    daily_limit: Option<String>,
+    max_withdrawals: Option<usize>,
+    window: Option<String>,
+}
+
+/// Limits per client with a fallback profile for everybody else
+#[derive(Debug, Clone, Default)]
+pub struct LimitProfiles {
+    pub default: WithdrawalLimits,
+    pub per_client: HashMap<u16, WithdrawalLimits>,
+}
+
+impl LimitProfiles {
+    /// Load a CSV of `client,max_withdrawal,daily_limit,max_withdrawals,window` rows.
+    /// `client` is a client id or `default`, empty cells mean no limit, e.g.
+    /// `default,1000,5000,10,1h`
+    pub fn load(path: &str) -> Result<Self, ApplicationError> {
+        let file = File::open(path)
+            .map_err(|e| ApplicationError::CouldNotOpenFile(format!("{}: {}", path, e)))?;
+
+        let mut reader = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(file);
+
+        let invalid = |client: &str, reason: String| ApplicationError::InvalidConfiguration(
+            format!("{}: limits for {}: {}", path, client, reason)
+        );
+        let amount = |client: &str, value: Option<String>| value
+            .map(|v| Decimal::from_str(&v).map_err(|e| invalid(client, format!("'{}': {}", v, e))))
+            .transpose();
+
+        let mut profiles = LimitProfiles::default();
+        for result in reader.deserialize() {
+            let row: LimitsRow = result
+                .map_err(|e| ApplicationError::InvalidConfiguration(format!("{}: {}", path, e)))?;
+
+            let velocity = match (row.max_withdrawals, row.window) {
+                (Some(max_count), Some(window)) => Some((max_count, parse_duration(&window)?)),
+                (None, None) => None,
+                _ => return Err(invalid(&row.client, "max_withdrawals and window go together".to_string())),
+            };
+            let limits = WithdrawalLimits {
+                max_single: amount(&row.client, row.max_withdrawal)?,
+                daily_total: amount(&row.client, row.daily_limit)?,
+                velocity,
+            };
+
+            if row.client == "default" {
+                profiles.default = limits;
+            } else {
+                let client_id = row.client.parse::<u16>()
+                    .map_err(|e| invalid(&row.client, e.to_string()))?;
+                profiles.per_client.insert(client_id, limits);
+            }
+        }
+
+        Ok(profiles)
+    }
+
+    pub fn for_client(&self, client_id: u16) -> &WithdrawalLimits {
+        self.per_client.get(&client_id).unwrap_or(&self.default)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_profiles() {
+        let profiles = LimitProfiles::load("tests/limits.csv").unwrap();
+
+        assert_eq!(profiles.for_client(1), &WithdrawalLimits {
+            max_single: Some(Decimal::from(1000)),
+            daily_total: Some(Decimal::from(1500)),
+            velocity: Some((3, TimeDelta::hours(1))),
+        });
+
+        // client 7 overrides the default and is only capped per withdrawal
+        assert_eq!(profiles.for_client(7), &WithdrawalLimits {
+            max_single: Some(Decimal::from(50000)),
+            daily_total: None,
+            velocity: None,
+        });
+    }
+}
@@ -1,81 +1,234 @@
-use std::{env, thread};
+use std::{env, io, thread};
+use std::fs::File;
 use std::sync::mpsc::channel;
-use drizzly::client::{new_clients_map};
+use drizzly::alerts::write_alerts;
+use drizzly::client::{write_balances, Balance};
+use drizzly::config::{parse_duration, Config};
+use drizzly::csv_ingestor::DEFAULT_BATCH_SIZE;
+use drizzly::currency::ExchangeRates;
 use drizzly::error::ApplicationError;
-use drizzly::csv_ingestor::{read_csv};
-use drizzly::dispatcher::Dispatcher;
-use drizzly::worker::spawn_workers;
+use drizzly::engine::{Engine, ShutdownReport};
+use drizzly::event_store::{Cutoff, DEFAULT_SNAPSHOT_INTERVAL};
+use drizzly::ledger::TrialBalance;
+use drizzly::limits::LimitProfiles;
+use drizzly::parallel_ingestor::ParallelRead;
+use drizzly::risk::parse_rule;
+use drizzly::statement::{build_statement, write_statement, StatementFormat};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: cargo run -- <path_to_csv>");
-        std::process::exit(1);
-    }
+const USAGE: &str = "Usage: cargo run -- [statement --client <id> [--format csv|text]] <path_to_csv> [--ordering arrival|reject|reorder:<window>] \
+[--dispute-window <duration>] [--dispute-expiry <duration>:resolve|chargeback] [--default-currency <code>] \
+[--rates <path_to_rates_csv>] [--limits <path_to_limits_csv>] [--risk <rule_spec>]... [--alerts <path_to_alerts_csv>] \
+[--freeze-open-disputes <count>] [--freeze-disputed-ratio <ratio>] \
+[--overdraft allow|reject|hold-available|collections:<limit>] [--balance-at <client>@<tx_id|timestamp>]... \
+[--snapshot-every <events>] [--batch-size <rows>] [--parse-threads <count>] [--mmap] [--trial-balance] [--audit]";
 
-    let csv_path = &args[1];
+/// Everything taken from the command line
+struct CliArgs {
+    csv_path: String,
+    config: Config,
+    rates_path: Option<String>,
+    alerts_path: Option<String>,
+    trial_balance: bool,
+    balance_queries: Vec<(u16, Cutoff)>,
+    snapshot_interval: usize,
+    batch_size: usize,
+    /// Set by `--parse-threads` or `--mmap`, parses the file in chunks on several threads
+    parallel_read: Option<ParallelRead>,
+    /// Set by the `statement` command, which prints one client's statement instead of every balance
+    statement: Option<(u16, StatementFormat)>,
+}
 
-    // Create shared clients map
-    let global_clients_map = new_clients_map();
+fn flag_value<'a>(iter: &mut impl Iterator<Item = &'a String>, flag: &str) -> Result<&'a String, ApplicationError> {
+    iter.next()
+        .ok_or_else(|| ApplicationError::InvalidConfiguration(format!("{} needs a value", flag)))
+}
 
-    // Error accumulator
-    let mut errors_list: Vec<ApplicationError> = Vec::new();
+/// Parse a `<client>@<tx_id|timestamp>` point-in-time balance query
+fn parse_balance_query(s: &str) -> Result<(u16, Cutoff), ApplicationError> {
+    let (client_id, cutoff) = s.split_once('@')
+        .ok_or_else(|| ApplicationError::InvalidConfiguration(format!("invalid balance query '{}', expected <client>@<tx_id|timestamp>", s)))?;
+    let client_id = client_id.parse()
+        .map_err(|e| ApplicationError::InvalidConfiguration(format!("invalid client in balance query '{}': {}", s, e)))?;
+    Ok((client_id, cutoff.parse()?))
+}
+
+/// Parse the csv path and optional flags
+fn parse_args(args: &[String]) -> Result<CliArgs, ApplicationError> {
+    let mut csv_path = None;
+    // invariants are always checked in debug builds
+    let mut config = Config { audit: cfg!(debug_assertions), ..Config::default() };
+    let mut rates_path = None;
+    let mut alerts_path = None;
+    let mut trial_balance = false;
+    let mut balance_queries = Vec::new();
+    let mut snapshot_interval = DEFAULT_SNAPSHOT_INTERVAL;
+    let mut batch_size = DEFAULT_BATCH_SIZE;
+    let mut parallel_read: Option<ParallelRead> = None;
+    let is_statement = args.get(1).is_some_and(|command| command == "statement");
+    let mut statement_client = None;
+    let mut statement_format = StatementFormat::default();
+
+    let mut iter = args.iter().skip(if is_statement { 2 } else { 1 });
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--ordering" => config.ordering = flag_value(&mut iter, arg)?.parse()?,
+            "--default-currency" => config.default_currency = flag_value(&mut iter, arg)?.parse()?,
+            "--dispute-window" => config.disputes.window = Some(parse_duration(flag_value(&mut iter, arg)?)?),
+            "--dispute-expiry" => config.disputes.expiry = Some(flag_value(&mut iter, arg)?.parse()?),
+            "--freeze-open-disputes" => config.disputes.abuse.max_open_disputes = Some(
+                flag_value(&mut iter, arg)?.parse()
+                    .map_err(|e| ApplicationError::InvalidConfiguration(format!("{}: {}", arg, e)))?
+            ),
+            "--freeze-disputed-ratio" => config.disputes.abuse.max_disputed_ratio = Some(
+                flag_value(&mut iter, arg)?.parse()
+                    .map_err(|e| ApplicationError::InvalidConfiguration(format!("{}: {}", arg, e)))?
+            ),
+            "--overdraft" => config.disputes.overdraft = flag_value(&mut iter, arg)?.parse()?,
+            "--rates" => rates_path = Some(flag_value(&mut iter, arg)?.to_string()),
+            "--limits" => config.limits = LimitProfiles::load(flag_value(&mut iter, arg)?)?,
+            "--risk" => config.risk_rules.push(parse_rule(flag_value(&mut iter, arg)?)?),
+            "--alerts" => alerts_path = Some(flag_value(&mut iter, arg)?.to_string()),
+            "--balance-at" => balance_queries.push(parse_balance_query(flag_value(&mut iter, arg)?)?),
+            "--snapshot-every" => snapshot_interval = flag_value(&mut iter, arg)?.parse()
+                .map_err(|e| ApplicationError::InvalidConfiguration(format!("{}: {}", arg, e)))?,
+            "--batch-size" => batch_size = flag_value(&mut iter, arg)?.parse()
+                .map_err(|e| ApplicationError::InvalidConfiguration(format!("{}: {}", arg, e)))?,
+            "--parse-threads" => parallel_read.get_or_insert_with(ParallelRead::default).parsers = flag_value(&mut iter, arg)?.parse()
+                .map_err(|e| ApplicationError::InvalidConfiguration(format!("{}: {}", arg, e)))?,
+            "--mmap" => parallel_read.get_or_insert_with(ParallelRead::default).mmap = true,
+            "--client" if is_statement => statement_client = Some(flag_value(&mut iter, arg)?.parse()
+                .map_err(|e| ApplicationError::InvalidConfiguration(format!("{}: {}", arg, e)))?),
+            "--format" if is_statement => statement_format = flag_value(&mut iter, arg)?.parse()?,
+            "--trial-balance" => trial_balance = true,
+            "--audit" => config.audit = true,
+            flag if flag.starts_with("--") => {
+                return Err(ApplicationError::InvalidConfiguration(format!("unknown flag {}", flag)));
+            }
+            path => csv_path = Some(path.to_string()),
+        }
+    }
+
+    let csv_path = csv_path
+        .ok_or_else(|| ApplicationError::InvalidConfiguration("missing csv path".to_string()))?;
+    let statement = match (is_statement, statement_client) {
+        (false, _) => None,
+        (true, Some(client_id)) => Some((client_id, statement_format)),
+        (true, None) => return Err(ApplicationError::InvalidConfiguration("statement needs --client".to_string())),
+    };
+    Ok(CliArgs { csv_path, config, rates_path, alerts_path, trial_balance, balance_queries, snapshot_interval, batch_size, parallel_read, statement })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let CliArgs { csv_path, config, rates_path, alerts_path, trial_balance, balance_queries, snapshot_interval, batch_size, parallel_read, statement } = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
 
-    // Spawn workers
-    let (worker_senders, worker_handles) = spawn_workers(global_clients_map.clone());
+    // Load exchange rates up front, foreign rows are converted into the default (base) currency
+    let exchange_rates = match rates_path.map(|path| ExchangeRates::load(&path, config.default_currency.clone())) {
+        Some(Ok(rates)) => Some(rates),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
 
-    // Create dispatcher
-    let (dispatcher_sender, ingestion_receiver) = channel();
-    let dispatcher = Dispatcher::new(worker_senders);
-    let dispatcher_handle = dispatcher.start(ingestion_receiver);
+    let default_currency = config.default_currency.clone();
 
-    // Spawn CSV ingestion
-    let csv_path_clone = csv_path.to_string();
-    let ingestion_handle = thread::spawn(move || {
-        read_csv(&csv_path_clone, dispatcher_sender)
+    // Alerts go to their own CSV, or to STD err when no file is given
+    let (alert_sender, alert_receiver) = channel();
+    let alerts_handle = thread::spawn(move || match alerts_path {
+        Some(path) => {
+            let file = File::create(&path)
+                .map_err(|e| ApplicationError::CouldNotOpenFile(format!("{}: {}", path, e)))?;
+            write_alerts(alert_receiver, file)
+        }
+        None => write_alerts(alert_receiver, io::stderr()),
     });
 
-    // Wait on CSV thread
-    match ingestion_handle.join() {
-        Ok(Ok(_)) => eprintln!("CSV ingestion thread finished"),
-        Ok(Err(e)) => errors_list.push(e),
-        Err(panic) => errors_list.push(
-            ApplicationError::Other(format!("CSV ingestion panic: {:?}", panic))
-        ),
+    let mut builder = Engine::builder()
+        .config(config)
+        .alerts(alert_sender)
+        .batch_size(batch_size)
+        .source(csv_path);
+    if let Some(exchange_rates) = exchange_rates {
+        builder = builder.exchange_rates(exchange_rates);
+    }
+    if let Some(parallel_read) = parallel_read {
+        builder = builder.parallel_read(parallel_read);
+    }
+    // Events are only kept when there are point-in-time queries or a statement to answer
+    // and only for the clients they are about
+    if !balance_queries.is_empty() || statement.is_some() {
+        let clients = balance_queries.iter().map(|(client_id, _)| *client_id).chain(statement.map(|(client_id, _)| client_id));
+        builder = builder.event_store(snapshot_interval).record_clients(clients);
     }
 
-    // Wait on dispatcher
-    match dispatcher_handle.join() {
-        Ok(Ok(())) => eprintln!("Dispatcher terminated"),
+    let engine = builder.build();
+    let config = engine.config().clone();
+    let event_store = engine.event_store().cloned();
+    // Error accumulator
+    let ShutdownReport { clients, ingestion, errors: mut errors_list } = engine.shutdown();
+    errors_list.extend(ingestion.into_iter().filter_map(Result::err).map(|e| e.error));
+
+    // Wait on alerts writer, it finishes once all workers dropped their senders
+    match alerts_handle.join() {
+        Ok(Ok(0)) => {}
+        Ok(Ok(count)) => eprintln!("{} alerts raised", count),
         Ok(Err(e)) => errors_list.push(e),
         Err(panic) => errors_list.push(
-            ApplicationError::Other(format!("Dispatcher panic: {:?}", panic))
+            ApplicationError::Other(format!("Alerts writer panic: {:?}", panic))
         ),
     }
 
-    // Wait on workers
-    for handle in worker_handles {
-        match handle.join() {
-            Ok(Ok(())) => eprintln!("Worker terminated"),
-            Ok(Err(e)) => errors_list.push(e),
-            Err(panic) => errors_list.push(
-                ApplicationError::Other(format!("Worker panic: {:?}", panic))
-            ),
+    // print the statement to STD output, or else global accounts ordered by client id
+    if let (Some((client_id, format)), Some(event_store)) = (statement, &event_store) {
+        let lines = build_statement(&event_store.read().unwrap(), client_id, &config);
+        if let Err(e) = write_statement(client_id, &lines, format, io::stdout()) {
+            errors_list.push(e);
+        }
+    } else {
+        if let Err(e) = write_balances(&clients, &default_currency, io::stdout()) {
+            errors_list.push(e);
         }
     }
 
-    // print global accounts to STD output
-    println!("client,available,held,total,locked");
+    // trial balance over every client's ledger to STD err
+    if trial_balance {
+        let mut trial_balance = TrialBalance::default();
+        for client in clients.values() {
+            trial_balance.add(&client.ledger);
+        }
+        if let Err(e) = trial_balance.write(io::stderr()) {
+            errors_list.push(e);
+        }
+    }
 
-    // unlock RWLock
-    let clients_guard = global_clients_map.read().unwrap();
-    for (id, client) in clients_guard.iter() {
-        // output all amounts in 4 decimal places
-        println!("{},{:.4},{:.4},{:.4},{}",
-                 id, client.available, client.held, client.total, client.locked
-        );
+    // point-in-time balances to STD err
+    if let Some(event_store) = event_store {
+        let event_store = event_store.read().unwrap();
+        eprintln!("client,cutoff,currency,available,held,total,state");
+        for (client_id, cutoff) in balance_queries {
+            let Some(client) = event_store.client_at(client_id, cutoff, &config) else {
+                eprintln!("{},{},,,,,unknown", client_id, cutoff);
+                continue;
+            };
+            let mut balances = client.balances();
+            if balances.is_empty() {
+                balances.insert(default_currency.clone(), Balance::default());
+            }
+            for (currency, balance) in balances {
+                eprintln!("{},{},{},{:.4},{:.4},{:.4},{}",
+                          client_id, cutoff, currency, balance.available, balance.held, balance.total, client.state);
+            }
+        }
     }
-    drop(clients_guard);
 
     // print error to STD error
     if !errors_list.is_empty() {
@@ -0,0 +1,386 @@
+///
+/// Module which ingests large CSV files by parsing chunks of them on several threads
+///
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
+use std::thread;
+use std::time::Instant;
+use memmap2::Mmap;
+use crate::csv_ingestor::{shift_error, CsvTransaction, IngestionError, IngestionStats, Transactions};
+use crate::currency::ExchangeRates;
+use crate::error::ApplicationError;
+
+/// Bytes per chunk unless configured otherwise, a chunk ends at the first record boundary after this many bytes
+pub const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+
+// extra bytes read at a time while looking for the end of a record past the chunk size
+const READ_AHEAD: usize = 64 * 1024;
+
+/// How `read_csv_parallel` splits and reads a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelRead {
+    /// Threads parsing chunks
+    pub parsers: usize,
+    pub chunk_size: usize,
+    /// Map the file into memory and parse chunks in place instead of reading them into buffers
+    pub mmap: bool,
+}
+
+impl Default for ParallelRead {
+    fn default() -> Self {
+        Self { parsers: num_cpus::get(), chunk_size: DEFAULT_CHUNK_SIZE, mmap: false }
+    }
+}
+
+/// Where `record_boundary` is within a field, as csv's reader sees it
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Start,
+    Unquoted,
+    Quoted,
+    /// A quote inside a quoted field, either closing it or escaping the next one
+    QuoteInQuoted,
+}
+
+/// Index just past the first record-ending newline at or after `min_len` bytes, None when `bytes` has none.
+/// `bytes` must start at a record boundary, newlines inside quoted fields do not end a record.
+fn record_boundary(bytes: &[u8], min_len: usize) -> Option<usize> {
+    let mut field = Field::Start;
+    for (index, &byte) in bytes.iter().enumerate() {
+        field = match (field, byte) {
+            (Field::Quoted, b'"') => Field::QuoteInQuoted,
+            (Field::Quoted, _) => Field::Quoted,
+            (Field::QuoteInQuoted, b'"') => Field::Quoted,
+            (_, b',') => Field::Start,
+            (_, b'\n') if index + 1 >= min_len => return Some(index + 1),
+            (_, b'\n') => Field::Start,
+            // only a quote opening a field quotes it, later ones are data as csv reads them
+            (Field::Start, b'"') => Field::Quoted,
+            _ => Field::Unquoted,
+        };
+    }
+    None
+}
+
+/// A run of whole records, `offset` being where it starts in the file
+struct Chunk<'a> {
+    seq: usize,
+    offset: u64,
+    bytes: Cow<'a, [u8]>,
+}
+
+/// Rows of a chunk in file order, up to the first row that failed to deserialize
+struct ParsedChunk {
+    offset: u64,
+    /// Bytes of the chunk read, up to the malformed row if any
+    len: u64,
+    /// Newlines in the chunk, quoted ones included as csv counts lines
+    lines: u64,
+    rows: Vec<CsvTransaction>,
+    /// Rows left out for lack of a usable exchange rate
+    rejected: u64,
+    error: Option<csv::Error>,
+}
+
+/// Where chunks are cut from: a mapped file is sliced in place, a read file is copied into buffers
+enum Source<'a> {
+    Mapped { data: &'a [u8], position: usize },
+    Read { file: File, buffer: Vec<u8>, position: u64, eof: bool },
+}
+
+impl<'a> Source<'a> {
+    /// Read until the buffer holds at least `len` bytes or the file ends
+    fn fill(file: &mut File, buffer: &mut Vec<u8>, eof: &mut bool, len: usize) -> io::Result<()> {
+        while !*eof && buffer.len() < len {
+            let read = file.by_ref().take((len - buffer.len()) as u64).read_to_end(buffer)?;
+            *eof = read == 0;
+        }
+        Ok(())
+    }
+
+    /// The header line, up to and including its newline
+    fn header(&mut self) -> io::Result<Vec<u8>> {
+        Ok(self.next_bytes(1)?.map(|(_, bytes)| bytes.into_owned()).unwrap_or_default())
+    }
+
+    /// Whole records starting at the current position, at least `chunk_size` bytes of them unless the file ends first
+    fn next_bytes(&mut self, chunk_size: usize) -> io::Result<Option<(u64, Cow<'a, [u8]>)>> {
+        match self {
+            Source::Mapped { data, position } => {
+                let rest = &data[*position..];
+                if rest.is_empty() {
+                    return Ok(None);
+                }
+                let len = record_boundary(rest, chunk_size).unwrap_or(rest.len());
+                let offset = *position as u64;
+                *position += len;
+                Ok(Some((offset, Cow::Borrowed(&rest[..len]))))
+            }
+            Source::Read { file, buffer, position, eof } => {
+                Self::fill(file, buffer, eof, chunk_size)?;
+                let len = loop {
+                    if let Some(len) = record_boundary(buffer, chunk_size) {
+                        break len;
+                    }
+                    if *eof {
+                        break buffer.len();
+                    }
+                    let wanted = buffer.len() + READ_AHEAD;
+                    Self::fill(file, buffer, eof, wanted)?;
+                };
+                if len == 0 {
+                    return Ok(None);
+                }
+
+                let rest = buffer.split_off(len);
+                let offset = *position;
+                *position += len as u64;
+                Ok(Some((offset, Cow::Owned(std::mem::replace(buffer, rest)))))
+            }
+        }
+    }
+}
+
+/// Cut the source into chunks, each waiting for a free slot so no more than the slots are in flight at once
+fn split<'a>(mut source: Source<'a>, chunk_size: usize, chunk_sender: SyncSender<Chunk<'a>>, slots: Receiver<()>) -> io::Result<()> {
+    let mut seq = 0;
+    while let Some((offset, bytes)) = source.next_bytes(chunk_size)? {
+        // the reading side stopped, nothing will free a slot any more
+        if slots.recv().is_err() || chunk_sender.send(Chunk { seq, offset, bytes }).is_err() {
+            break;
+        }
+        seq += 1;
+    }
+    Ok(())
+}
+
+/// Deserialize a chunk as rows of a file made of the header and the chunk
+fn parse_chunk(header: &[u8], chunk: &Chunk<'_>, exchange_rates: Option<&ExchangeRates>) -> ParsedChunk {
+    let lines = chunk.bytes.iter().filter(|&&byte| byte == b'\n').count() as u64;
+    let mut parsed = ParsedChunk { offset: chunk.offset, len: chunk.bytes.len() as u64, lines, rows: Vec::new(), rejected: 0, error: None };
+
+    let mut transactions = Transactions::new(header.chain(chunk.bytes.as_ref()));
+    loop {
+        let row_start = transactions.bytes_read();
+        let Some(result) = transactions.next() else { break };
+        let mut csv_transaction = match result {
+            Ok(csv_transaction) => csv_transaction,
+            Err(e) => {
+                parsed.len = row_start - header.len() as u64;
+                parsed.error = Some(e);
+                break;
+            }
+        };
+
+        if let Some(exchange_rates) = exchange_rates
+            && let Err(e) = exchange_rates.convert(&mut csv_transaction)
+        {
+            eprintln!("WARNING: skipping row, {}", e);
+            parsed.rejected += 1;
+            continue;
+        }
+        parsed.rows.push(csv_transaction);
+    }
+
+    parsed
+}
+
+/// Read CSV like `read_csv`, but with the file split at record boundaries into chunks that `options.parsers`
+/// threads deserialize in parallel. Parsed chunks are put back in file order before their rows are sent on,
+/// so every client's transactions still reach their worker in the order of the file.
+/// At most two chunks per parser are in flight at any time, which bounds memory whatever the size of the file.
+pub fn read_csv_parallel<T: From<Vec<CsvTransaction>>>(csv_path: &str, dispatcher_sender: SyncSender<T>, exchange_rates: Option<&ExchangeRates>, batch_size: usize, options: ParallelRead) -> Result<IngestionStats, IngestionError> {
+    let started = Instant::now();
+    let mut stats = IngestionStats::default();
+    let result = read_chunks(csv_path, dispatcher_sender, exchange_rates, batch_size, options, &mut stats);
+    stats.finish(started, result)
+}
+
+fn read_chunks<T: From<Vec<CsvTransaction>>>(csv_path: &str, dispatcher_sender: SyncSender<T>, exchange_rates: Option<&ExchangeRates>, batch_size: usize, options: ParallelRead, stats: &mut IngestionStats) -> Result<(), ApplicationError> {
+    let file = File::open(csv_path)
+        .map_err(|e| ApplicationError::CouldNotOpenFile(format!("{}: {}", csv_path, e)))?;
+    let io_error = |e: io::Error| ApplicationError::CouldNotOpenFile(format!("{}: {}", csv_path, e));
+
+    // SAFETY: the input must not be modified while it is being read, as with any mapped file
+    let mapped = options.mmap.then(|| unsafe { Mmap::map(&file) }).transpose().map_err(io_error)?;
+    let mut source = match &mapped {
+        Some(mapped) => Source::Mapped { data: mapped, position: 0 },
+        None => Source::Read { file, buffer: Vec::new(), position: 0, eof: false },
+    };
+    let header = source.header().map_err(io_error)?;
+
+    let parsers = options.parsers.max(1);
+    let in_flight = parsers * 2;
+    let chunk_size = options.chunk_size.max(1);
+    let batch_size = batch_size.max(1);
+    let send = |batch: Vec<CsvTransaction>| dispatcher_sender
+        .send(batch.into())
+        .map_err(|e| ApplicationError::Other(format!("Dispatcher channel closed: {}", e)));
+
+    // shared by the parsers, each takes the next chunk as soon as it is done with the previous one
+    let (chunk_sender, chunk_receiver) = sync_channel(in_flight);
+    let chunk_receiver = Mutex::new(chunk_receiver);
+
+    thread::scope(|scope| {
+        let (slot_sender, slot_receiver) = sync_channel(in_flight);
+        let (parsed_sender, parsed_receiver) = channel();
+        for _ in 0..in_flight {
+            let _ = slot_sender.send(());
+        }
+
+        let splitter = scope.spawn(move || split(source, chunk_size, chunk_sender, slot_receiver));
+
+        for _ in 0..parsers {
+            let (chunk_receiver, parsed_sender, header) = (&chunk_receiver, parsed_sender.clone(), &header);
+            scope.spawn(move || {
+                // the lock is only held while waiting for the next chunk
+                while let Ok(chunk) = chunk_receiver.lock().unwrap().recv() {
+                    if parsed_sender.send((chunk.seq, parse_chunk(header, &chunk, exchange_rates))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(parsed_sender);
+
+        stats.bytes = header.len() as u64;
+        let mut batch = Vec::with_capacity(batch_size);
+        // chunks parsed ahead of the one due next
+        let mut parsed_ahead = BTreeMap::new();
+        let mut next_seq = 0;
+        // lines of the file before the chunk due next, the header's aside
+        let mut lines_before = 0;
+
+        for (seq, parsed) in parsed_receiver {
+            parsed_ahead.insert(seq, parsed);
+
+            while let Some(ParsedChunk { offset, len, lines, rows, rejected, error }) = parsed_ahead.remove(&next_seq) {
+                next_seq += 1;
+                let _ = slot_sender.send(());
+                let records_before = stats.rows_read;
+                stats.rows_read += rows.len() as u64 + rejected;
+                stats.rows_rejected += rejected;
+                stats.bytes += len;
+
+                for csv_transaction in rows {
+                    batch.push(csv_transaction);
+                    if batch.len() == batch_size {
+                        send(std::mem::replace(&mut batch, Vec::with_capacity(batch_size)))?;
+                    }
+                }
+
+                // rows before a malformed one still go out, the rest of the file does not
+                if let Some(e) = error {
+                    if !batch.is_empty() {
+                        send(batch)?;
+                    }
+                    // a chunk is parsed as a file of the header and the chunk, what came before it is put back
+                    let e = shift_error(e, records_before, lines_before, offset - header.len() as u64);
+                    return Err(ApplicationError::FailedDeserializedCsvTransaction(format!("{}: {}", csv_path, e)));
+                }
+                lines_before += lines;
+            }
+        }
+
+        if !batch.is_empty() {
+            send(batch)?;
+        }
+        match splitter.join() {
+            Ok(result) => result.map_err(io_error)?,
+            Err(panic) => return Err(ApplicationError::Other(format!("CSV splitter panic: {:?}", panic))),
+        }
+        Ok(())
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_ingestor::{read_csv, TestSink, DEFAULT_BATCH_SIZE};
+
+    #[test]
+    fn test_record_boundary() {
+        assert_eq!(record_boundary(b"a,b\nc,d\n", 1), Some(4));
+        assert_eq!(record_boundary(b"a,b\nc,d\n", 5), Some(8));
+        assert_eq!(record_boundary(b"a,b\nc,d", 5), None);
+        // quoted newlines and escaped quotes stay inside their record
+        assert_eq!(record_boundary(b"a,\"b\nc\"\nd\n", 1), Some(8));
+        assert_eq!(record_boundary(b"a,\"b\"\"\n\"\nd\n", 1), Some(9));
+        // quotes past the start of a field, or after a closing quote, are data
+        assert_eq!(record_boundary(b"a,b\"c\nd\n", 1), Some(6));
+        assert_eq!(record_boundary(b"a,\"b\"c\"\nd\n", 1), Some(8));
+        assert_eq!(record_boundary(b"a,b\"c\n\"d\ne\"\nf\n", 7), Some(12));
+    }
+
+    #[test]
+    fn test_matches_sequential_read() {
+        let exchange_rates = ExchangeRates::load("tests/rates.csv", "EUR".parse().unwrap()).unwrap();
+
+        for path in ["tests/transactions.csv", "tests/admin.csv", "tests/quoted.csv", "tests/currencies.csv", "tests/timestamps.csv"] {
+            let rates = (path == "tests/currencies.csv").then_some(&exchange_rates);
+            let (sender, sink) = TestSink::new();
+            let expected_stats = read_csv(path, sender, rates, DEFAULT_BATCH_SIZE).unwrap();
+            let expected = sink.rows();
+
+            // chunks of a few records up to the whole file, read and mapped
+            for chunk_size in [1, 40, 100, 1 << 20] {
+                for mmap in [false, true] {
+                    let options = ParallelRead { parsers: 3, chunk_size, mmap };
+                    let (sender, sink) = TestSink::new();
+                    let stats = read_csv_parallel(path, sender, rates, 2, options).unwrap();
+
+                    assert_eq!(sink.rows(), expected, "{} in chunks of {} bytes, mmap {}", path, chunk_size, mmap);
+                    assert_eq!(
+                        (stats.rows_read, stats.rows_rejected, stats.bytes),
+                        (expected_stats.rows_read, expected_stats.rows_rejected, expected_stats.bytes),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_malformed_row_stops_reading() {
+        for mmap in [false, true] {
+            let (sender, sink) = TestSink::new();
+            let options = ParallelRead { parsers: 2, chunk_size: 1, mmap };
+            let err = read_csv_parallel("tests/malformed.csv", sender, None, DEFAULT_BATCH_SIZE, options).unwrap_err();
+
+            assert!(matches!(err.error, ApplicationError::FailedDeserializedCsvTransaction(_)));
+            assert_eq!(err.stats.rows_read, 1);
+            let sent: Vec<u32> = sink.rows().iter().map(|tx| tx.tx_id).collect();
+            assert_eq!(sent, vec![1001]);
+        }
+    }
+
+    #[test]
+    fn test_errors_report_file_positions() {
+        let dir = std::env::temp_dir().join(format!("drizzly-parallel-errors-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rows = "deposit,1,1,1.0,\nfreeze,1,2,,\"lost\ncard\"\ndeposit,2,3,2.0,\nunlock,1,4,,\"found\"\n";
+        let malformed: [&[u8]; 3] = [b"deposit,2,5,x,\n", b"deposit,2,5\n", b"deposit,2,5,1.0,\xff\n"];
+
+        for (index, bad_row) in malformed.into_iter().enumerate() {
+            let path = dir.join(format!("{}.csv", index));
+            std::fs::write(&path, [b"type,client,tx,amount,reason\n".as_slice(), rows.as_bytes(), bad_row, b"deposit,2,6,1.0,\n"].concat()).unwrap();
+            let path = path.to_str().unwrap();
+            let expected = read_csv(path, TestSink::new().0, None, DEFAULT_BATCH_SIZE).unwrap_err();
+            let message = expected.error.to_string();
+            assert!(message.contains("record 5 (line 7") || message.contains("record 5 (line: 7"), "{}", message);
+
+            for chunk_size in [1, 40, 1 << 20] {
+                let options = ParallelRead { parsers: 2, chunk_size, mmap: false };
+                let err = read_csv_parallel(path, TestSink::new().0, None, DEFAULT_BATCH_SIZE, options).unwrap_err();
+                assert_eq!(err.error.to_string(), message, "in chunks of {} bytes", chunk_size);
+                // the rows before the malformed one, as far as a sequential read got
+                assert_eq!((err.stats.rows_read, err.stats.bytes), (expected.stats.rows_read, expected.stats.bytes));
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use crate::audit::ClientSnapshot;
+use crate::client::Client;
+use crate::config::Config;
+use crate::csv_ingestor::{CsvTransaction, TransactionType};
+use crate::dispute::ExpiryAction;
+use crate::risk::RiskDecision;
+use crate::sequencer::Sequencer;
+
+/// Applies transactions in the order given on the calling thread, calling the `Client` operations directly
+/// in a loop of its own: no dispatcher, channels or worker in between. The threaded pipeline must end up
+/// in the same state for any input, which makes this the reference to test it against.
+/// Events still go through a `Sequencer`, the ordering policy is not what is under test here.
+///
+/// Dispute expiry is the one exception: each worker only sees time move with its own clients' events,
+/// so overdue disputes may be settled later than here when there is more than one worker.
+pub struct ReferenceEngine {
+    config: Arc<Config>,
+    sequencer: Sequencer,
+    clients: HashMap<u16, Client>,
+    // overdue disputes already looked at as (client, tx, raised at), one left open because of the account state stays open
+    swept: HashSet<(u16, u32, DateTime<Utc>)>,
+}
+
+impl ReferenceEngine {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            sequencer: Sequencer::new(config.ordering.clone()),
+            config,
+            clients: HashMap::new(),
+            swept: HashSet::new(),
+        }
+    }
+
+    /// Apply a transaction, or hold it back as a worker's sequencer would
+    pub fn apply(&mut self, mut csv_transaction: CsvTransaction) {
+        csv_transaction.currency.get_or_insert_with(|| self.config.default_currency.clone());
+        // refused events never reach the client
+        for csv_transaction in self.sequencer.push(csv_transaction).unwrap_or_default() {
+            self.apply_in_order(&csv_transaction);
+        }
+    }
+
+    /// State and balances of every client so far
+    pub fn snapshot(&self) -> BTreeMap<u16, ClientSnapshot> {
+        self.clients.iter()
+            .map(|(client_id, client)| (*client_id, ClientSnapshot::of(client)))
+            .collect()
+    }
+
+    /// Apply whatever is held back and hand over the clients
+    pub fn finish(mut self) -> HashMap<u16, Client> {
+        for csv_transaction in self.sequencer.drain() {
+            self.apply_in_order(&csv_transaction);
+        }
+        self.clients
+    }
+
+    fn apply_in_order(&mut self, csv_transaction: &CsvTransaction) {
+        self.settle_overdue_disputes(csv_transaction);
+
+        let config = &self.config;
+        let client = self.clients.entry(csv_transaction.client_id).or_default();
+        if csv_transaction.tx_type.is_admin() {
+            let _ = client.change_state(csv_transaction);
+            return;
+        }
+        let denied = config.risk_rules.iter()
+            .any(|rule| matches!(rule.evaluate(client, csv_transaction), RiskDecision::Deny(_)));
+        if !client.state.allows(&csv_transaction.tx_type) || denied {
+            return;
+        }
+
+        // a rejected transaction leaves the client as it was
+        let _ = match csv_transaction.tx_type {
+            TransactionType::Deposit => client.deposit(csv_transaction),
+            TransactionType::Withdrawal => client.withdraw(csv_transaction, config.limits.for_client(csv_transaction.client_id)),
+            TransactionType::Dispute => client.dispute(csv_transaction, &config.disputes).map(|_| {
+                client.freeze_on_dispute_abuse(csv_transaction, &config.disputes.abuse);
+            }),
+            TransactionType::Resolve => client.resolve(csv_transaction),
+            TransactionType::Chargeback => client.chargeback(csv_transaction),
+            TransactionType::Refund => client.refund(csv_transaction),
+            TransactionType::Unlock |
+            TransactionType::Freeze |
+            TransactionType::Close |
+            TransactionType::Reopen => unreachable!("admin operations are handled above"),
+        };
+    }
+
+    /// Settle every open dispute that is past its deadline at the time of `csv_transaction`, earliest deadline first
+    fn settle_overdue_disputes(&mut self, csv_transaction: &CsvTransaction) {
+        let (Some(expiry), Some(now)) = (self.config.disputes.expiry, csv_transaction.timestamp) else {
+            return;
+        };
+
+        let mut overdue: Vec<(DateTime<Utc>, u16, u32, DateTime<Utc>)> = self.clients.iter()
+            .flat_map(|(client_id, client)| client.disputes.iter()
+                .flat_map(move |(tx_id, portions)| portions.iter()
+                    .filter_map(move |open| open.raised_at.map(|raised_at| (raised_at + expiry.max_open, *client_id, *tx_id, raised_at)))))
+            .filter(|(deadline, client_id, tx_id, raised_at)| *deadline < now && !self.swept.contains(&(*client_id, *tx_id, *raised_at)))
+            .collect();
+        overdue.sort();
+
+        let tx_type = match expiry.action {
+            ExpiryAction::Resolve => TransactionType::Resolve,
+            ExpiryAction::Chargeback => TransactionType::Chargeback,
+        };
+        for (_, client_id, tx_id, raised_at) in overdue {
+            self.swept.insert((client_id, tx_id, raised_at));
+            let client = self.clients.get_mut(&client_id).unwrap();
+            if client.state.allows(&tx_type) {
+                client.settle_expired(tx_id, raised_at, expiry.action, Some(now));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeDelta, Utc};
+    use rust_decimal::Decimal;
+    use crate::csv_ingestor::TransactionType;
+    use crate::dispute::{AbuseThresholds, DisputePolicy, OverdraftPolicy};
+    use crate::engine::Engine;
+    use crate::generator::Rng;
+    use crate::risk::parse_rule;
+    use crate::sequencer::OrderingPolicy;
+
+    /// A stream mixing every transaction type over a few clients, with references to earlier
+    /// deposits of the same client and timestamps that are mostly, but not always, increasing
+    fn random_stream(seed: u64, len: u32) -> Vec<CsvTransaction> {
+        let mut rng = Rng::new(seed);
+        let start = DateTime::parse_from_rfc3339("2026-03-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        let mut deposits: HashMap<u16, Vec<u32>> = HashMap::new();
+
+        (1..=len).map(|tx_id| {
+            let client_id = rng.below(12) as u16 + 1;
+            let earlier = deposits.get(&client_id)
+                .filter(|ids| !ids.is_empty())
+                .map(|ids| ids[rng.below(ids.len() as u64) as usize]);
+            let amount = Decimal::new(rng.below(50_000) as i64 + 1, 2);
+
+            let (tx_type, tx_id, amount) = match (rng.below(10), earlier) {
+                (0..=3, _) | (_, None) => (TransactionType::Deposit, tx_id, Some(amount)),
+                (4..=5, _) => (TransactionType::Withdrawal, tx_id, Some(amount)),
+                (6, Some(earlier)) => (TransactionType::Dispute, earlier, None),
+                (7, Some(earlier)) => (TransactionType::Resolve, earlier, None),
+                (8, Some(earlier)) if rng.below(4) == 0 => (TransactionType::Chargeback, earlier, None),
+                (_, Some(earlier)) => (TransactionType::Refund, earlier, None),
+            };
+            if tx_type == TransactionType::Deposit {
+                deposits.entry(client_id).or_default().push(tx_id);
+            }
+
+            // a few events arrive up to an hour late
+            let jitter = if rng.below(10) == 0 { -(rng.below(3600) as i64) } else { 0 };
+            CsvTransaction {
+                tx_type,
+                client_id,
+                tx_id,
+                amount,
+                timestamp: Some(start + TimeDelta::minutes(i64::from(tx_id)) + TimeDelta::seconds(jitter)),
+                currency: None,
+                reason: None,
+                conversion: None,
+            }
+        }).collect()
+    }
+
+    fn assert_same_final_state(config: Config, workers: usize) {
+        let config = Arc::new(config);
+
+        for seed in 0..20 {
+            let stream = random_stream(seed, 400);
+
+            let mut reference = ReferenceEngine::new(config.clone());
+            for tx in stream.iter().cloned() {
+                reference.apply(tx);
+            }
+            let expected: BTreeMap<u16, ClientSnapshot> = reference.finish().iter()
+                .map(|(client_id, client)| (*client_id, ClientSnapshot::of(client)))
+                .collect();
+
+            let engine = Engine::builder()
+                .workers(workers)
+                .channel_capacity(8)
+                .config((*config).clone())
+                .build();
+            // odd seeds go in as batches of varying size, split per worker by the dispatcher
+            if seed % 2 == 0 {
+                for tx in stream {
+                    engine.submit(tx).unwrap();
+                }
+            } else {
+                for batch in stream.chunks(seed as usize * 3) {
+                    engine.submit_batch(batch.to_vec()).unwrap();
+                }
+            }
+            engine.flush().unwrap();
+            assert_eq!(engine.snapshot(), expected, "seed {} with {} workers", seed, workers);
+            engine.shutdown();
+        }
+    }
+
+    #[test]
+    fn test_matches_threaded_pipeline() {
+        assert_same_final_state(Config::default(), 4);
+    }
+
+    #[test]
+    fn test_matches_threaded_pipeline_with_policies() {
+        let config = Config {
+            ordering: OrderingPolicy::Reorder { window: 3 },
+            disputes: DisputePolicy {
+                window: Some(TimeDelta::hours(2)),
+                overdraft: OverdraftPolicy::Collections { overdraft_limit: Decimal::from(100) },
+                ..DisputePolicy::default()
+            },
+            ..Config::default()
+        };
+        assert_same_final_state(config, 3);
+    }
+
+    #[test]
+    fn test_matches_threaded_pipeline_with_expiry_and_risk() {
+        // a single worker sees time move with every event, as the reference does
+        let config = Config {
+            disputes: DisputePolicy {
+                expiry: Some("3h:chargeback".parse().unwrap()),
+                abuse: AbuseThresholds { max_open_disputes: Some(3), ..AbuseThresholds::default() },
+                ..DisputePolicy::default()
+            },
+            risk_rules: vec![parse_rule("large-deposit-withdrawal:400:30m:deny").unwrap()],
+            ..Config::default()
+        };
+        assert_same_final_state(config, 1);
+    }
+}
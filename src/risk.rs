@@ -0,0 +1,198 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use chrono::TimeDelta;
+use rust_decimal::Decimal;
+use crate::client::Client;
+use crate::config::parse_duration;
+use crate::csv_ingestor::{CsvTransaction, TransactionType};
+use crate::error::ApplicationError;
+
+/// Outcome of a risk rule for one incoming transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RiskDecision {
+    Allow,
+    /// Apply the transaction and raise an alert with the given detail
+    Flag(String),
+    /// Refuse the transaction and raise an alert with the given detail
+    Deny(String),
+}
+
+/// A check workers run against the client's current state before applying a transaction.
+/// Rules are shared by all workers so they must not keep per-client state of their own.
+pub trait RiskRule: Send + Sync + Debug {
+    fn name(&self) -> &str;
+    fn evaluate(&self, client: &Client, tx: &CsvTransaction) -> RiskDecision;
+}
+
+fn decide(deny: bool, detail: String) -> RiskDecision {
+    if deny { RiskDecision::Deny(detail) } else { RiskDecision::Flag(detail) }
+}
+
+/// A withdrawal following a deposit of at least `min_deposit` within `window`
+#[derive(Debug)]
+pub struct LargeDepositThenWithdrawal {
+    pub min_deposit: Decimal,
+    pub window: TimeDelta,
+    pub deny: bool,
+}
+
+impl RiskRule for LargeDepositThenWithdrawal {
+    fn name(&self) -> &str {
+        "large-deposit-withdrawal"
+    }
+
+    fn evaluate(&self, client: &Client, tx: &CsvTransaction) -> RiskDecision {
+        let TransactionType::Withdrawal = tx.tx_type else { return RiskDecision::Allow };
+        let Some(deposit) = client.last_deposit.and_then(|id| client.tx_history.get(&id)) else {
+            return RiskDecision::Allow;
+        };
+
+        match (deposit.amount, deposit.timestamp, tx.timestamp) {
+            (Some(amount), Some(deposited_at), Some(now))
+                if amount >= self.min_deposit && now - deposited_at <= self.window =>
+            {
+                decide(self.deny, format!(
+                    "withdrawal {}s after deposit tx {} of {}", (now - deposited_at).num_seconds(), deposit.tx_id, amount
+                ))
+            }
+            _ => RiskDecision::Allow,
+        }
+    }
+}
+
+/// A client raising more than `max_disputes` disputes over their lifetime
+#[derive(Debug)]
+pub struct RepeatedDisputes {
+    pub max_disputes: u32,
+    pub deny: bool,
+}
+
+impl RiskRule for RepeatedDisputes {
+    fn name(&self) -> &str {
+        "repeated-disputes"
+    }
+
+    fn evaluate(&self, client: &Client, tx: &CsvTransaction) -> RiskDecision {
+        match tx.tx_type {
+            TransactionType::Dispute if client.disputes_raised >= self.max_disputes => decide(self.deny, format!(
+                "dispute number {}, threshold {}", client.disputes_raised + 1, self.max_disputes
+            )),
+            _ => RiskDecision::Allow,
+        }
+    }
+}
+
+/// A chargeback arriving within `window` of its dispute being raised
+#[derive(Debug)]
+pub struct RapidChargeback {
+    pub window: TimeDelta,
+    pub deny: bool,
+}
+
+impl RiskRule for RapidChargeback {
+    fn name(&self) -> &str {
+        "rapid-chargeback"
+    }
+
+    fn evaluate(&self, client: &Client, tx: &CsvTransaction) -> RiskDecision {
+        let TransactionType::Chargeback = tx.tx_type else { return RiskDecision::Allow };
+
+        match (client.open_dispute(tx.tx_id, tx.amount).and_then(|open| open.raised_at), tx.timestamp) {
+            (Some(raised_at), Some(now)) if now - raised_at <= self.window => decide(self.deny, format!(
+                "chargeback {}s after the dispute was raised", (now - raised_at).num_seconds()
+            )),
+            _ => RiskDecision::Allow,
+        }
+    }
+}
+
+/// Build a built-in rule from a command line spec, append `:deny` to refuse instead of flag:
+/// `large-deposit-withdrawal:<amount>:<duration>`, `repeated-disputes:<count>`, `rapid-chargeback:<duration>`
+pub fn parse_rule(spec: &str) -> Result<Arc<dyn RiskRule>, ApplicationError> {
+    let invalid = || ApplicationError::InvalidConfiguration(format!("invalid risk rule '{}'", spec));
+
+    let (spec_body, deny) = match spec.strip_suffix(":deny") {
+        Some(body) => (body, true),
+        None => (spec, false),
+    };
+    let parts: Vec<&str> = spec_body.split(':').collect();
+
+    match parts.as_slice() {
+        ["large-deposit-withdrawal", min_deposit, window] => Ok(Arc::new(LargeDepositThenWithdrawal {
+            min_deposit: min_deposit.parse().map_err(|_| invalid())?,
+            window: parse_duration(window)?,
+            deny,
+        })),
+        ["repeated-disputes", max_disputes] => Ok(Arc::new(RepeatedDisputes {
+            max_disputes: max_disputes.parse().map_err(|_| invalid())?,
+            deny,
+        })),
+        ["rapid-chargeback", window] => Ok(Arc::new(RapidChargeback {
+            window: parse_duration(window)?,
+            deny,
+        })),
+        _ => Err(invalid()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+    use crate::dispute::DisputePolicy;
+
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!(parse_rule("large-deposit-withdrawal:10000:1h").unwrap().name(), "large-deposit-withdrawal");
+        assert_eq!(parse_rule("repeated-disputes:3:deny").unwrap().name(), "repeated-disputes");
+        assert_eq!(parse_rule("rapid-chargeback:10m").unwrap().name(), "rapid-chargeback");
+
+        assert!(parse_rule("repeated-disputes").is_err());
+        assert!(parse_rule("repeated-disputes:many").is_err());
+        assert!(parse_rule("unknown:1").is_err());
+    }
+
+    #[test]
+    fn test_large_deposit_then_withdrawal() {
+        let rule = parse_rule("large-deposit-withdrawal:1000:1h").unwrap();
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(5000).at("2026-03-01T08:00:00Z")).unwrap();
+
+        let quick = tx(TransactionType::Withdrawal, 1, 2).with_amount(4900).at("2026-03-01T08:30:00Z");
+        assert!(matches!(rule.evaluate(&client, &quick), RiskDecision::Flag(_)));
+
+        let later = tx(TransactionType::Withdrawal, 1, 3).with_amount(4900).at("2026-03-01T10:00:00Z");
+        assert_eq!(rule.evaluate(&client, &later), RiskDecision::Allow);
+    }
+
+    #[test]
+    fn test_repeated_disputes() {
+        let rule = parse_rule("repeated-disputes:1:deny").unwrap();
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(10).at("2026-03-01T08:00:00Z")).unwrap();
+        client.deposit(&tx(TransactionType::Deposit, 1, 2).with_amount(10).at("2026-03-01T08:00:00Z")).unwrap();
+
+        let first = tx(TransactionType::Dispute, 1, 1).at("2026-03-01T09:00:00Z");
+        assert_eq!(rule.evaluate(&client, &first), RiskDecision::Allow);
+        client.dispute(&first, &DisputePolicy::default()).unwrap();
+
+        let second = tx(TransactionType::Dispute, 1, 2).at("2026-03-01T09:00:00Z");
+        assert!(matches!(rule.evaluate(&client, &second), RiskDecision::Deny(_)));
+    }
+
+    #[test]
+    fn test_rapid_chargeback() {
+        let rule = parse_rule("rapid-chargeback:10m").unwrap();
+        let mut client = Client::default();
+        client.deposit(&tx(TransactionType::Deposit, 1, 1).with_amount(10).at("2026-03-01T08:00:00Z")).unwrap();
+        client.dispute(&tx(TransactionType::Dispute, 1, 1).at("2026-03-01T09:00:00Z"), &DisputePolicy::default()).unwrap();
+
+        let rapid = tx(TransactionType::Chargeback, 1, 1).at("2026-03-01T09:05:00Z");
+        assert!(matches!(rule.evaluate(&client, &rapid), RiskDecision::Flag(_)));
+
+        let slow = tx(TransactionType::Chargeback, 1, 1).at("2026-03-02T09:00:00Z");
+        assert_eq!(rule.evaluate(&client, &slow), RiskDecision::Allow);
+    }
+}
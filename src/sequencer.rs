@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use crate::csv_ingestor::CsvTransaction;
+use crate::error::ApplicationError;
+
+/// How a worker treats events whose timestamp is older than an event it already saw for the same client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum OrderingPolicy {
+    /// Apply events in file order, timestamps are informational only
+    #[default]
+    Arrival,
+    /// Refuse events older than the latest timestamp applied for the client
+    Reject,
+    /// Buffer up to `window` timestamped events per client and release them in timestamp order.
+    /// Events arriving older than something already released are refused.
+    Reorder { window: usize },
+}
+
+impl FromStr for OrderingPolicy {
+    type Err = ApplicationError;
+
+    /// Accepts `arrival`, `reject` or `reorder:<window>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "arrival" => Ok(OrderingPolicy::Arrival),
+            "reject" => Ok(OrderingPolicy::Reject),
+            _ => {
+                let window = s
+                    .strip_prefix("reorder:")
+                    .and_then(|w| w.parse::<usize>().ok())
+                    .filter(|w| *w > 0)
+                    .ok_or_else(|| ApplicationError::InvalidConfiguration(
+                        format!("unknown ordering policy '{}', expected arrival, reject or reorder:<window>", s)
+                    ))?;
+                Ok(OrderingPolicy::Reorder { window })
+            }
+        }
+    }
+}
+
+/// Per-client event-time sequencing in front of a worker.
+/// Each worker owns one, as a client's transactions always land on the same worker.
+#[derive(Debug, Default)]
+pub struct Sequencer {
+    policy: OrderingPolicy,
+    // pending events kept sorted by timestamp, only used by Reorder
+    pending: HashMap<u16, Vec<CsvTransaction>>,
+    latest: HashMap<u16, DateTime<Utc>>,
+}
+
+impl Sequencer {
+    pub fn new(policy: OrderingPolicy) -> Self {
+        Self { policy, ..Default::default() }
+    }
+
+    /// Accept an incoming event and return the events that are ready to be applied, in order
+    pub fn push(&mut self, tx: CsvTransaction) -> Result<Vec<CsvTransaction>, ApplicationError> {
+        let Some(timestamp) = tx.timestamp else {
+            // events without a time cannot be placed, so flush whatever is waiting ahead of them
+            let mut ready = self.pending.remove(&tx.client_id).unwrap_or_default();
+            if let Some(last) = ready.last().and_then(|t| t.timestamp) {
+                self.latest.insert(tx.client_id, last);
+            }
+            ready.push(tx);
+            return Ok(ready);
+        };
+
+        if self.policy != OrderingPolicy::Arrival
+            && self.latest.get(&tx.client_id).is_some_and(|latest| timestamp < *latest)
+        {
+            return Err(ApplicationError::OutOfOrderTransaction(tx.client_id, tx.tx_id));
+        }
+
+        match self.policy {
+            OrderingPolicy::Arrival => Ok(vec![tx]),
+            OrderingPolicy::Reject => {
+                self.latest.insert(tx.client_id, timestamp);
+                Ok(vec![tx])
+            }
+            OrderingPolicy::Reorder { window } => {
+                let client_id = tx.client_id;
+                let pending = self.pending.entry(client_id).or_default();
+
+                // insert after any event with an equal timestamp to keep file order for ties
+                let position = pending.partition_point(|t| t.timestamp <= Some(timestamp));
+                pending.insert(position, tx);
+
+                if pending.len() > window {
+                    let released = pending.remove(0);
+                    if let Some(released_at) = released.timestamp {
+                        self.latest.insert(client_id, released_at);
+                    }
+                    Ok(vec![released])
+                } else {
+                    Ok(Vec::new())
+                }
+            }
+        }
+    }
+
+    /// Release every buffered event, used once the input stream is exhausted or flushed
+    pub fn drain(&mut self) -> Vec<CsvTransaction> {
+        let mut ready = Vec::new();
+        for (client_id, pending) in self.pending.drain() {
+            if let Some(last) = pending.last().and_then(|t| t.timestamp) {
+                self.latest.insert(client_id, last);
+            }
+            ready.extend(pending);
+        }
+        ready
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+    use crate::csv_ingestor::TransactionType;
+
+
+    fn ids(txs: &[CsvTransaction]) -> Vec<u32> {
+        txs.iter().map(|tx| tx.tx_id).collect()
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!("arrival".parse::<OrderingPolicy>().unwrap(), OrderingPolicy::Arrival);
+        assert_eq!("reject".parse::<OrderingPolicy>().unwrap(), OrderingPolicy::Reject);
+        assert_eq!("reorder:3".parse::<OrderingPolicy>().unwrap(), OrderingPolicy::Reorder { window: 3 });
+        assert!("reorder:0".parse::<OrderingPolicy>().is_err());
+        assert!("sometimes".parse::<OrderingPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_arrival_keeps_file_order() {
+        let mut sequencer = Sequencer::new(OrderingPolicy::Arrival);
+
+        assert_eq!(ids(&sequencer.push(tx(TransactionType::Deposit, 1, 1).at("2026-03-01T10:00:00Z")).unwrap()), vec![1]);
+        assert_eq!(ids(&sequencer.push(tx(TransactionType::Deposit, 1, 2).at("2026-03-01T09:00:00Z")).unwrap()), vec![2]);
+    }
+
+    #[test]
+    fn test_reject_older_events_per_client() {
+        let mut sequencer = Sequencer::new(OrderingPolicy::Reject);
+
+        sequencer.push(tx(TransactionType::Deposit, 1, 1).at("2026-03-01T10:00:00Z")).unwrap();
+        // another client is unaffected
+        sequencer.push(tx(TransactionType::Deposit, 2, 2).at("2026-03-01T08:00:00Z")).unwrap();
+
+        let err = sequencer.push(tx(TransactionType::Deposit, 1, 3).at("2026-03-01T09:00:00Z")).unwrap_err();
+        match err {
+            ApplicationError::OutOfOrderTransaction(client_id, tx_id) => {
+                assert_eq!(client_id, 1);
+                assert_eq!(tx_id, 3);
+            }
+            _ => panic!("Expected OutOfOrderTransaction error"),
+        }
+    }
+
+    #[test]
+    fn test_reorder_within_window() {
+        let mut sequencer = Sequencer::new(OrderingPolicy::Reorder { window: 2 });
+
+        assert!(sequencer.push(tx(TransactionType::Deposit, 1, 1).at("2026-03-01T10:00:00Z")).unwrap().is_empty());
+        assert!(sequencer.push(tx(TransactionType::Deposit, 1, 2).at("2026-03-01T08:00:00Z")).unwrap().is_empty());
+        assert_eq!(ids(&sequencer.push(tx(TransactionType::Deposit, 1, 3).at("2026-03-01T09:00:00Z")).unwrap()), vec![2]);
+
+        // older than what was already released, too late to reorder
+        assert!(sequencer.push(tx(TransactionType::Deposit, 1, 4).at("2026-03-01T07:00:00Z")).is_err());
+
+        // an event without a timestamp flushes the buffer ahead of itself
+        assert_eq!(ids(&sequencer.push(tx(TransactionType::Deposit, 1, 5)).unwrap()), vec![3, 1, 5]);
+        assert!(sequencer.drain().is_empty());
+    }
+
+    #[test]
+    fn test_drain_releases_pending() {
+        let mut sequencer = Sequencer::new(OrderingPolicy::Reorder { window: 10 });
+
+        sequencer.push(tx(TransactionType::Deposit, 1, 1).at("2026-03-01T10:00:00Z")).unwrap();
+        sequencer.push(tx(TransactionType::Deposit, 1, 2).at("2026-03-01T09:00:00Z")).unwrap();
+
+        assert_eq!(ids(&sequencer.drain()), vec![2, 1]);
+    }
+}
@@ -0,0 +1,236 @@
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+use crate::client::{Balance, Client};
+use crate::config::Config;
+use crate::csv_ingestor::TransactionType;
+use crate::currency::Currency;
+use crate::error::ApplicationError;
+use crate::event_store::{EventSource, EventStore, StoredEvent};
+use crate::lifecycle::AccountState;
+use crate::worker::replay;
+
+/// Layout of a written statement
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatementFormat {
+    #[default]
+    Csv,
+    /// Aligned columns for people to read
+    Text,
+}
+
+impl FromStr for StatementFormat {
+    type Err = ApplicationError;
+
+    /// Accepts `csv` or `text`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(StatementFormat::Csv),
+            "text" => Ok(StatementFormat::Text),
+            _ => Err(ApplicationError::InvalidConfiguration(
+                format!("unknown statement format '{}', expected csv or text", s)
+            )),
+        }
+    }
+}
+
+/// One event of a client's statement with the client's balance right after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementLine {
+    pub event: StoredEvent,
+    /// Currency the event moved funds in, the original tx's for disputes, resolves, chargebacks and refunds
+    pub currency: Currency,
+    pub balance: Balance,
+    pub state: AccountState,
+}
+
+impl StatementLine {
+    fn status(&self) -> &'static str {
+        if self.event.rejection.is_some() { "rejected" } else { "applied" }
+    }
+
+    fn detail(&self) -> String {
+        match (&self.event.rejection, self.event.source) {
+            (Some(rejection), _) => rejection.clone(),
+            (None, EventSource::DisputeExpiry { .. }) => "dispute expired".to_string(),
+            (None, _) => self.event.tx.reason.clone().unwrap_or_default(),
+        }
+    }
+
+    fn amount(&self) -> String {
+        self.event.tx.amount.map_or_else(String::new, |amount| format!("{:.4}", amount))
+    }
+}
+
+/// Every event of `client_id` in the order its worker applied them, rejected ones included,
+/// with running balances rebuilt by replaying the client's events one at a time.
+/// `config` must be the one the events were processed with.
+pub fn build_statement(event_store: &EventStore, client_id: u16, config: &Arc<Config>) -> Vec<StatementLine> {
+    let mut client = Client::default();
+
+    event_store.events(client_id).iter()
+        .map(|event| {
+            client = replay(config, client_id, std::mem::take(&mut client), std::slice::from_ref(event));
+
+            let tx = &event.tx;
+            let currency = match tx.tx_type {
+                TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback | TransactionType::Refund =>
+                    client.tx_history.get(&tx.tx_id).and_then(|original| original.currency.clone()),
+                _ => None,
+            }.or_else(|| tx.currency.clone()).unwrap_or_default();
+
+            StatementLine {
+                event: event.clone(),
+                balance: client.balance(&currency),
+                currency,
+                state: client.state,
+            }
+        })
+        .collect()
+}
+
+/// Write a statement as CSV or as aligned text
+pub fn write_statement(client_id: u16, lines: &[StatementLine], format: StatementFormat, mut writer: impl Write) -> Result<(), ApplicationError> {
+    let io_error = |e: std::io::Error| ApplicationError::Other(format!("Could not write statement: {}", e));
+
+    match format {
+        StatementFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            csv_writer.write_record(["seq", "tx", "type", "timestamp", "amount", "currency", "status", "detail", "available", "held", "total", "state"])
+                .map_err(|e| ApplicationError::Other(format!("Could not write statement: {}", e)))?;
+            for (seq, line) in lines.iter().enumerate() {
+                let tx = &line.event.tx;
+                csv_writer.write_record([
+                    (seq + 1).to_string(),
+                    tx.tx_id.to_string(),
+                    tx.tx_type.to_string(),
+                    tx.timestamp.map_or_else(String::new, |t| t.to_rfc3339()),
+                    line.amount(),
+                    line.currency.to_string(),
+                    line.status().to_string(),
+                    line.detail(),
+                    format!("{:.4}", line.balance.available),
+                    format!("{:.4}", line.balance.held),
+                    format!("{:.4}", line.balance.total),
+                    line.state.to_string(),
+                ]).map_err(|e| ApplicationError::Other(format!("Could not write statement: {}", e)))?;
+            }
+            csv_writer.flush().map_err(io_error)?;
+        }
+        StatementFormat::Text => {
+            writeln!(writer, "Statement for client {}, {} events", client_id, lines.len()).map_err(io_error)?;
+            writeln!(writer, "{:>6}  {:>10}  {:<10}  {:<25}  {:>14}  {:<3}  {:<8}  {:>14}  {:>14}  {:>14}  {:<11}  detail",
+                     "seq", "tx", "type", "timestamp", "amount", "cur", "status", "available", "held", "total", "state").map_err(io_error)?;
+            for (seq, line) in lines.iter().enumerate() {
+                let tx = &line.event.tx;
+                writeln!(writer, "{:>6}  {:>10}  {:<10}  {:<25}  {:>14}  {:<3}  {:<8}  {:>14.4}  {:>14.4}  {:>14.4}  {:<11}  {}",
+                         seq + 1,
+                         tx.tx_id,
+                         tx.tx_type.to_string(),
+                         tx.timestamp.map_or_else(String::new, |t| t.to_rfc3339()),
+                         line.amount(),
+                         line.currency.to_string(),
+                         line.status(),
+                         line.balance.available,
+                         line.balance.held,
+                         line.balance.total,
+                         line.state.to_string(),
+                         line.detail(),
+                ).map_err(io_error)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::tx;
+    use rust_decimal::Decimal;
+    use crate::csv_ingestor::CsvTransaction;
+
+
+    fn store_of(events: &[(CsvTransaction, Option<&str>)]) -> EventStore {
+        let mut store = EventStore::default();
+        for (tx, rejection) in events {
+            store.append(&Client::default(), tx, EventSource::Input, rejection.map(str::to_string));
+        }
+        store
+    }
+
+    #[test]
+    fn test_statement_running_balances() {
+        let config = Arc::new(Config::default());
+        let store = store_of(&[
+            (tx(TransactionType::Deposit, 7, 1).with_amount(100), None),
+            (tx(TransactionType::Withdrawal, 7, 2).with_amount(500), Some("insufficient")),
+            (tx(TransactionType::Dispute, 7, 1), None),
+            (tx(TransactionType::Chargeback, 7, 1), None),
+        ]);
+
+        let lines = build_statement(&store, 7, &config);
+        let running: Vec<(&str, Decimal, Decimal, AccountState)> = lines.iter()
+            .map(|line| (line.status(), line.balance.available, line.balance.held, line.state))
+            .collect();
+        assert_eq!(running, vec![
+            ("applied", Decimal::from(100), Decimal::ZERO, AccountState::Active),
+            ("rejected", Decimal::from(100), Decimal::ZERO, AccountState::Active),
+            ("applied", Decimal::ZERO, Decimal::from(100), AccountState::Active),
+            ("applied", Decimal::ZERO, Decimal::ZERO, AccountState::Locked),
+        ]);
+        assert!(build_statement(&store, 8, &config).is_empty());
+    }
+
+    #[test]
+    fn test_no_ops_are_rejected() {
+        use crate::engine::Engine;
+
+        let engine = Engine::builder().workers(1).event_store(10).build();
+        for tx in [
+            tx(TransactionType::Deposit, 7, 1).with_amount(100),
+            tx(TransactionType::Resolve, 7, 1),
+            tx(TransactionType::Chargeback, 7, 1),
+            tx(TransactionType::Dispute, 7, 9),
+            tx(TransactionType::Refund, 7, 9).with_amount(10),
+        ] {
+            engine.submit(tx).unwrap();
+        }
+        engine.flush().unwrap();
+
+        let lines = build_statement(&engine.event_store().unwrap().read().unwrap(), 7, engine.config());
+        let statuses: Vec<&str> = lines.iter().map(StatementLine::status).collect();
+        assert_eq!(statuses, vec!["applied", "rejected", "rejected", "rejected", "rejected"]);
+        assert!(lines[1].detail().contains("no open dispute"));
+        assert_eq!(lines.last().unwrap().balance.available, Decimal::from(100));
+        engine.shutdown();
+    }
+
+    #[test]
+    fn test_write_statement() {
+        let config = Arc::new(Config::default());
+        let store = store_of(&[
+            (tx(TransactionType::Deposit, 7, 1).with_amount(100), None),
+            (tx(TransactionType::Withdrawal, 7, 2).with_amount(500), Some("insufficient")),
+        ]);
+        let lines = build_statement(&store, 7, &config);
+
+        let mut csv = Vec::new();
+        write_statement(7, &lines, StatementFormat::Csv, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "seq,tx,type,timestamp,amount,currency,status,detail,available,held,total,state\n\
+             1,1,deposit,,100.0000,USD,applied,,100.0000,0.0000,100.0000,active\n\
+             2,2,withdrawal,,500.0000,USD,rejected,insufficient,100.0000,0.0000,100.0000,active\n"
+        );
+
+        let mut text = Vec::new();
+        write_statement(7, &lines, StatementFormat::Text, &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("Statement for client 7, 2 events\n"));
+        assert_eq!(text.lines().count(), 4);
+        assert!(text.lines().last().unwrap().ends_with("insufficient"));
+    }
+}
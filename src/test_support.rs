@@ -0,0 +1,39 @@
+///
+/// Module of builders shared by the unit tests
+///
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use crate::csv_ingestor::{CsvTransaction, TransactionType};
+
+/// A `tx_type` row of `client_id`, without amount, timestamp, currency or reason until given one
+pub(crate) fn tx(tx_type: TransactionType, client_id: u16, tx_id: u32) -> CsvTransaction {
+    CsvTransaction {
+        tx_type,
+        client_id,
+        tx_id,
+        amount: None,
+        timestamp: None,
+        currency: None,
+        reason: None,
+        conversion: None,
+    }
+}
+
+impl CsvTransaction {
+    pub(crate) fn with_amount(self, amount: impl Into<Decimal>) -> Self {
+        Self { amount: Some(amount.into()), ..self }
+    }
+
+    /// The row's event time, written as RFC3339
+    pub(crate) fn at(self, timestamp: &str) -> Self {
+        Self { timestamp: Some(DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc)), ..self }
+    }
+
+    pub(crate) fn with_currency(self, currency: &str) -> Self {
+        Self { currency: Some(currency.parse().unwrap()), ..self }
+    }
+
+    pub(crate) fn with_reason(self, reason: &str) -> Self {
+        Self { reason: Some(reason.to_string()), ..self }
+    }
+}
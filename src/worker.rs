@@ -1,27 +1,47 @@
-use std::sync::mpsc::{Receiver, Sender};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
 use std::thread::JoinHandle;
-use crate::client::{GlobalClientsMap};
+use chrono::{DateTime, Utc};
+use crate::alerts::{Alert, AlertKind};
+use crate::audit::{check_invariants, ClientSnapshot};
+use crate::client::{new_clients_map, Client, GlobalClientsMap};
+use crate::config::Config;
 use crate::csv_ingestor::{CsvTransaction, TransactionType};
+use crate::dispatcher::Command;
+use crate::dispute::{ExpiryAction, ExpiryQueue};
 use crate::error::ApplicationError;
+use crate::event_store::{EventSource, SharedEventStore, StoredEvent};
+use crate::risk::RiskDecision;
+use crate::sequencer::Sequencer;
 
 // to make types simpler
-type WorkerSender = Sender<CsvTransaction>;
+type WorkerSender = SyncSender<Command>;
 type WorkerHandle = JoinHandle<Result<(), ApplicationError>>;
+type ClientsMap = HashMap<u16, Client>;
 
-/// Spawn worker threads for parallel processing
-/// Used as initialization method in main.rs
-pub fn spawn_workers(global_clients_map: GlobalClientsMap) -> (Vec<WorkerSender>, Vec<WorkerHandle>) {
-    let num_workers = num_cpus::get();
+/// Spawn `num_workers` worker threads for parallel processing, each reading a channel holding up to `channel_capacity` commands.
+/// Used as initialization method by the engine.
+/// With an `event_store`, every event applied to a client is recorded in it.
+pub fn spawn_workers(num_workers: usize, channel_capacity: usize, global_clients_map: GlobalClientsMap, config: Arc<Config>, alert_sender: Sender<Alert>, event_store: Option<SharedEventStore>) -> (Vec<WorkerSender>, Vec<WorkerHandle>) {
     let mut worker_senders = Vec::with_capacity(num_workers);
     let mut worker_handles = Vec::with_capacity(num_workers);
 
     for worker_id in 0..num_workers {
-        let (sender, receiver) = std::sync::mpsc::channel();
+        let (sender, receiver) = sync_channel(channel_capacity);
         worker_senders.push(sender);
 
-        let clients_ref = global_clients_map.clone();
+        let worker = Worker {
+            worker_id,
+            clients: global_clients_map.clone(),
+            sequencer: Sequencer::new(config.ordering.clone()),
+            expiries: ExpiryQueue::default(),
+            config: config.clone(),
+            alerts: alert_sender.clone(),
+            event_store: event_store.clone(),
+        };
         let handle= std::thread::spawn(move || {
-            process_transaction(worker_id, receiver, clients_ref)
+            worker.process_transaction(receiver)
         });
 
         worker_handles.push(handle);
@@ -30,46 +50,359 @@ pub fn spawn_workers(global_clients_map: GlobalClientsMap) -> (Vec<WorkerSender>
     (worker_senders, worker_handles)
 }
 
-/// Each worker processes transactions sequentially for the particular client (see dispatcher.rs for client_id -> worker index mapping.
-/// Argument worker_id is for debugging purposes
-fn process_transaction(worker_id: usize, worker_receiver: Receiver<CsvTransaction>, clients: GlobalClientsMap) -> Result<(), ApplicationError> {
-    for csv_transaction in worker_receiver {
+/// State owned by one worker thread
+pub(crate) struct Worker {
+    // for debugging purposes
+    worker_id: usize,
+    pub(crate) clients: GlobalClientsMap,
+    sequencer: Sequencer,
+    expiries: ExpiryQueue,
+    config: Arc<Config>,
+    alerts: Sender<Alert>,
+    event_store: Option<SharedEventStore>,
+}
+
+impl Worker {
+    /// A worker with a clients map of its own, driven directly instead of through a channel
+    pub(crate) fn standalone(config: Arc<Config>, alerts: Sender<Alert>) -> Self {
+        Worker {
+            worker_id: 0,
+            clients: new_clients_map(),
+            sequencer: Sequencer::new(config.ordering.clone()),
+            expiries: ExpiryQueue::default(),
+            config,
+            alerts,
+            event_store: None,
+        }
+    }
+
+    /// Each worker processes transactions sequentially for the particular client (see dispatcher.rs for client_id -> worker index mapping.
+    fn process_transaction(mut self, worker_receiver: Receiver<Command>) -> Result<(), ApplicationError> {
+        for command in worker_receiver {
+            match command {
+                Command::Transaction(csv_transaction) => self.receive(csv_transaction),
+                Command::Batch(batch) => self.receive_batch(batch),
+                Command::Flush(ack) => {
+                    self.drain();
+                    // the flushing side may have given up waiting
+                    let _ = ack.send(());
+                }
+            }
+        }
+
+        // input is exhausted, apply whatever the sequencer still holds
+        self.drain();
+        Ok(())
+    }
+
+    /// Incoming transactions pass through the worker's sequencer first, which may hold them back to restore event-time order.
+    pub(crate) fn receive(&mut self, csv_transaction: CsvTransaction) {
+        let clients = self.clients.clone();
+        let mut clients_map = clients.write().unwrap();
+        self.sequence(&mut clients_map, csv_transaction);
+    }
+
+    /// Receive a batch in order, holding the clients map lock for the whole batch instead of once per transaction
+    pub(crate) fn receive_batch(&mut self, batch: Vec<CsvTransaction>) {
+        let clients = self.clients.clone();
+        let mut clients_map = clients.write().unwrap();
+        for csv_transaction in batch {
+            self.sequence(&mut clients_map, csv_transaction);
+        }
+    }
+
+    fn sequence(&mut self, clients_map: &mut ClientsMap, mut csv_transaction: CsvTransaction) {
+        if csv_transaction.currency.is_none() {
+            csv_transaction.currency = Some(self.config.default_currency.clone());
+        }
+
+        // the sequencer consumes the event, keep a copy to record it if it is refused
+        let refused = self.event_store.is_some().then(|| csv_transaction.clone());
+        let ready = match self.sequencer.push(csv_transaction) {
+            Ok(ready) => ready,
+            Err(e) => {
+                eprintln!("[Worker {}] Error sequencing transaction: {}", self.worker_id, e);
+                if let Some(refused) = refused {
+                    self.record_refused(clients_map, &refused, &e);
+                }
+                return;
+            }
+        };
+
+        for csv_transaction in ready {
+            self.handle(clients_map, &csv_transaction);
+        }
+    }
+
+    /// Apply whatever the sequencer holds back
+    pub(crate) fn drain(&mut self) {
+        let clients = self.clients.clone();
+        let mut clients_map = clients.write().unwrap();
+        for csv_transaction in self.sequencer.drain() {
+            self.handle(&mut clients_map, &csv_transaction);
+        }
+    }
+
+    fn handle(&mut self, clients_map: &mut ClientsMap, csv_transaction: &CsvTransaction) {
+        self.sweep_expired_disputes(clients_map, csv_transaction);
+        // only a dispute that was applied has anything to expire
+        if self.apply_transaction(clients_map, csv_transaction) {
+            self.schedule_dispute_expiry(csv_transaction);
+        }
+    }
+
+    /// Remember when a dispute raised by `csv_transaction` becomes overdue
+    fn schedule_dispute_expiry(&mut self, csv_transaction: &CsvTransaction) {
+        if let (Some(expiry), Some(raised_at), TransactionType::Dispute) =
+            (self.config.disputes.expiry, csv_transaction.timestamp, &csv_transaction.tx_type)
+        {
+            self.expiries.schedule(raised_at + expiry.max_open, csv_transaction.client_id, csv_transaction.tx_id);
+        }
+    }
+
+    /// Settle the disputes that are overdue by the time of `csv_transaction`, before it is applied.
+    /// Time only moves with the event timestamps this worker sees.
+    fn sweep_expired_disputes(&mut self, clients_map: &mut ClientsMap, csv_transaction: &CsvTransaction) {
+        let (Some(expiry), Some(now)) = (self.config.disputes.expiry, csv_transaction.timestamp) else {
+            return;
+        };
+
+        let due = self.expiries.due(now);
+        if due.is_empty() {
+            return;
+        }
+
+        for (deadline, client_id, tx_id) in due {
+            let Some(client) = clients_map.get_mut(&client_id) else { continue };
+
+            // find the portion the deadline was scheduled for, skipping disputes that were settled,
+            // or settled and raised again, since
+            let overdue = client.disputes.get(&tx_id).into_iter().flatten()
+                .find(|open| open.raised_at.map(|raised_at| raised_at + expiry.max_open) == Some(deadline))
+                .and_then(|open| Some((open.raised_at?, open.amount)));
+            let Some((raised_at, amount)) = overdue else { continue };
+
+            let tx_type = match expiry.action {
+                ExpiryAction::Resolve => TransactionType::Resolve,
+                ExpiryAction::Chargeback => TransactionType::Chargeback,
+            };
+            if !client.state.allows(&tx_type) {
+                eprintln!("[Worker {}] Overdue dispute left open, client account is {}. More info: client-id {}, tx-id {}",
+                          self.worker_id, client.state, client_id, tx_id);
+                continue;
+            }
+
+            eprintln!("[Worker {}] Dispute open too long, applying {:?}. More info: client-id {}, tx-id {}",
+                      self.worker_id, expiry.action, client_id, tx_id);
+            // the settlement is recorded and audited as if it had arrived as a row of its own
+            let settlement = CsvTransaction {
+                tx_type,
+                client_id,
+                tx_id,
+                amount: Some(amount),
+                reason: Some("dispute expired".to_string()),
+                ..csv_transaction.clone()
+            };
+            let before = self.config.audit.then(|| ClientSnapshot::of(client));
+            settle_expired(client, &settlement, raised_at);
+
+            if let Some(before) = before {
+                self.report_violations(&before, client, &settlement);
+            }
+            self.record(client, &settlement, EventSource::DisputeExpiry { raised_at }, None);
+        }
+    }
+
+    fn record(&self, client: &Client, csv_transaction: &CsvTransaction, source: EventSource, rejection: Option<String>) {
+        if let Some(event_store) = &self.event_store {
+            event_store.write().unwrap().append(client, csv_transaction, source, rejection);
+        }
+    }
+
+    /// Record an event that never reached its client
+    fn record_refused(&self, clients_map: &ClientsMap, csv_transaction: &CsvTransaction, e: &ApplicationError) {
+        let unseen = Client::default();
+        let client = clients_map.get(&csv_transaction.client_id).unwrap_or(&unseen);
+        self.record(client, csv_transaction, EventSource::Refused, Some(e.to_string()));
+    }
+
+    /// Run the configured risk rules, alerting on every denial.
+    /// Flags are collected into `flags` as (rule, detail), they only concern transactions that end up applied.
+    /// Returns the name of the first rule that denied the transaction.
+    fn evaluate_risk(&self, client: &Client, csv_transaction: &CsvTransaction, flags: &mut Vec<(String, String)>) -> Option<String> {
+        let mut denied_by = None;
+
+        for rule in &self.config.risk_rules {
+            match rule.evaluate(client, csv_transaction) {
+                RiskDecision::Allow => {}
+                RiskDecision::Flag(detail) => flags.push((rule.name().to_string(), detail)),
+                RiskDecision::Deny(detail) => {
+                    denied_by.get_or_insert_with(|| rule.name().to_string());
+                    self.alert(csv_transaction, AlertKind::Deny, rule.name(), detail);
+                }
+            }
+        }
+
+        denied_by
+    }
+
+    fn report_violations(&self, before: &ClientSnapshot, client: &Client, csv_transaction: &CsvTransaction) {
+        for violation in check_invariants(before, client, csv_transaction, self.config.disputes.overdraft.overdraft_limit()) {
+            self.alert(csv_transaction, AlertKind::Violation, violation.invariant, violation.to_string());
+        }
+    }
+
+    fn alert(&self, csv_transaction: &CsvTransaction, kind: AlertKind, source: &str, detail: String) {
+        // alerts are best effort, nobody may be listening
+        let _ = self.alerts.send(Alert {
+            client_id: csv_transaction.client_id,
+            tx_id: csv_transaction.tx_id,
+            kind,
+            source: source.to_string(),
+            detail,
+        });
+    }
+
+    /// Apply a single transaction to its client, creating the client on first sight.
+    /// Returns whether it was applied, a refused transaction is logged and recorded.
+    fn apply_transaction(&self, clients_map: &mut ClientsMap, csv_transaction: &CsvTransaction) -> bool {
         let client_id = csv_transaction.client_id;
         let tx_id = csv_transaction.tx_id;
 
         // println!(
         //     "[Worker {}] Processing client {} transaction {}",
-        //     worker_id, client_id, tx_id
+        //     self.worker_id, client_id, tx_id
         // );
 
-        let mut clients_map = clients.write().unwrap();
         let client = clients_map.entry(csv_transaction.client_id).or_default();
 
-        if client.locked {
-            return Err(ApplicationError::ClientAccountFrozen(client_id,tx_id));
-        }
+        let before = self.config.audit.then(|| ClientSnapshot::of(client));
+        let mut flags = Vec::new();
 
-        let result = match csv_transaction.tx_type {
-            TransactionType::Deposit => {
-                client.deposit(&csv_transaction);
-                Ok(())
+        // admin operations check their own transitions, everything else must be allowed by the account state
+        // and get past the risk rules
+        let result = if csv_transaction.tx_type.is_admin() {
+            client.change_state(csv_transaction)
+        } else if !client.state.allows(&csv_transaction.tx_type) {
+            Err(ApplicationError::TransactionNotAllowed(client_id, tx_id, client.state))
+        } else if let Some(rule) = self.evaluate_risk(client, csv_transaction, &mut flags) {
+            Err(ApplicationError::RiskDenied(client_id, tx_id, rule))
+        } else {
+            match csv_transaction.tx_type {
+                TransactionType::Deposit => client.deposit(csv_transaction),
+                TransactionType::Withdrawal => client.withdraw(csv_transaction, self.config.limits.for_client(client_id)),
+                TransactionType::Dispute => match client.dispute(csv_transaction, &self.config.disputes) {
+                    Ok(outcome) => {
+                        if let Some(outcome) = outcome {
+                            self.alert(csv_transaction, AlertKind::Overdraft, "overdraft-policy", outcome.to_string());
+                        }
+                        if let Some(stats) = client.freeze_on_dispute_abuse(csv_transaction, &self.config.disputes.abuse) {
+                            self.alert(csv_transaction, AlertKind::Freeze, "auto-freeze", format!(
+                                "open disputes {} (threshold {}), disputed ratio {} (threshold {})",
+                                stats.open_disputes,
+                                display_threshold(self.config.disputes.abuse.max_open_disputes),
+                                stats.disputed_ratio.round_dp(4),
+                                display_threshold(self.config.disputes.abuse.max_disputed_ratio),
+                            ));
+                        }
+                        Ok(())
+                    }
+                    Err(e @ ApplicationError::InsufficientAvailableBalanceForDispute(..)) => {
+                        self.alert(csv_transaction, AlertKind::Deny, "overdraft-policy", "dispute exceeds the available balance".to_string());
+                        Err(e)
+                    }
+                    Err(e) => Err(e),
+                },
+                TransactionType::Resolve => client.resolve(csv_transaction),
+                TransactionType::Chargeback => client.chargeback(csv_transaction),
+                TransactionType::Refund => client.refund(csv_transaction),
+                TransactionType::Unlock |
+                TransactionType::Freeze |
+                TransactionType::Close |
+                TransactionType::Reopen => unreachable!("admin operations are handled above"),
             }
-            TransactionType::Withdrawal => client.withdraw(&csv_transaction),
-            TransactionType::Dispute => { client.dispute(csv_transaction.tx_id); Ok(()) },
-            TransactionType::Resolve => { client.resolve(csv_transaction.tx_id); Ok(()) },
-            TransactionType::Chargeback => { client.chargeback(csv_transaction.tx_id); Ok(()) },
         };
 
-        if let Err(e) = result {
-            eprintln!("[Worker {}] Error processing transaction {}: {}", worker_id, csv_transaction.tx_id, e);
-            continue; // do not return the error as it will cause the channel to close
+        if let Some(before) = before {
+            self.report_violations(&before, client, csv_transaction);
+        }
+        // a flag raised on a transaction that was refused after all has nothing left to look at
+        if result.is_ok() {
+            for (rule, detail) in flags {
+                self.alert(csv_transaction, AlertKind::Flag, &rule, detail);
+            }
+        }
+        // rejected events are kept too, replay rejects them again
+        self.record(client, csv_transaction, EventSource::Input, result.as_ref().err().map(ApplicationError::to_string));
+
+        if let Err(e) = &result {
+            eprintln!("[Worker {}] Error processing transaction {}: {}", self.worker_id, csv_transaction.tx_id, e);
+            // do not return the error as it will cause the channel to close
         }
 
         // println!(
         //     "[Worker {} successfully processed transaction id {}]",
-        //     worker_id, csv_transaction.tx_id
+        //     self.worker_id, csv_transaction.tx_id
         // );
+        result.is_ok()
+    }
+}
+
+/// Apply the resolve or chargeback a worker issued for the overdue dispute raised at `raised_at`
+fn settle_expired(client: &mut Client, settlement: &CsvTransaction, raised_at: DateTime<Utc>) {
+    let action = match settlement.tx_type {
+        TransactionType::Chargeback => ExpiryAction::Chargeback,
+        _ => ExpiryAction::Resolve,
+    };
+    client.settle_expired(settlement.tx_id, raised_at, action, settlement.timestamp);
+}
+
+/// Rebuild a client by applying stored `events` to `client` the way its worker did.
+/// Nothing is recorded and alerts raised along the way are dropped.
+pub(crate) fn replay(config: &Arc<Config>, client_id: u16, client: Client, events: &[StoredEvent]) -> Client {
+    let worker = Worker::standalone(config.clone(), channel().0);
+    let mut clients_map = HashMap::from([(client_id, client)]);
+
+    for event in events {
+        match event.source {
+            EventSource::Input => {
+                worker.apply_transaction(&mut clients_map, &event.tx);
+            }
+            EventSource::DisputeExpiry { raised_at } => settle_expired(clients_map.entry(client_id).or_default(), &event.tx, raised_at),
+            EventSource::Refused => {}
+        }
     }
 
-    Ok(())
-}
\ No newline at end of file
+    clients_map.remove(&client_id).unwrap_or_default()
+}
+
+fn display_threshold<T: ToString>(threshold: Option<T>) -> String {
+    threshold.map_or_else(|| "none".to_string(), |t| t.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+    use crate::dispute::DisputePolicy;
+    use crate::test_support::tx;
+
+    #[test]
+    fn test_only_applied_disputes_are_scheduled() {
+        let disputes = DisputePolicy { window: Some(TimeDelta::days(30)), expiry: Some("1d:resolve".parse().unwrap()), ..DisputePolicy::default() };
+        let mut worker = Worker::standalone(Arc::new(Config { disputes, ..Config::default() }), channel().0);
+
+        worker.receive(tx(TransactionType::Deposit, 1, 1).with_amount(100).at("2026-03-01T00:00:00Z"));
+        worker.receive(tx(TransactionType::Deposit, 1, 2).with_amount(100).at("2026-03-01T00:00:00Z"));
+        // an unknown tx and one past the window are refused
+        worker.receive(tx(TransactionType::Dispute, 1, 9).at("2026-03-02T00:00:00Z"));
+        worker.receive(tx(TransactionType::Dispute, 1, 2).at("2026-05-01T00:00:00Z"));
+        assert!(worker.expiries.is_empty());
+
+        worker.receive(tx(TransactionType::Dispute, 1, 1).at("2026-03-02T00:00:00Z"));
+        // already fully disputed
+        worker.receive(tx(TransactionType::Dispute, 1, 1).at("2026-03-02T01:00:00Z"));
+        assert_eq!(worker.expiries.len(), 1);
+    }
+}
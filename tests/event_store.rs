@@ -0,0 +1,73 @@
+//! The event store keeps memory linear in the events of a client, however many snapshots it takes along the way.
+//! Live heap bytes are counted by the global allocator, hence a test binary of its own with a single test.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rust_decimal::Decimal;
+use drizzly::client::Client;
+use drizzly::csv_ingestor::{CsvTransaction, TransactionType};
+use drizzly::event_store::{EventSource, EventStore};
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+            LIVE.fetch_add(new_size, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Heap bytes per event held by a store and its live client after `events` deposits of a single client
+fn bytes_per_event(events: u32) -> usize {
+    let before = LIVE.load(Ordering::Relaxed);
+    let mut store = EventStore::new(100);
+    let mut client = Client::default();
+    for tx_id in 1..=events {
+        let tx = CsvTransaction {
+            tx_type: TransactionType::Deposit,
+            client_id: 1,
+            tx_id,
+            amount: Some(Decimal::ONE),
+            timestamp: None,
+            currency: None,
+            reason: None,
+            conversion: None,
+        };
+        client.deposit(&tx).unwrap();
+        store.append(&client, &tx, EventSource::Input, None);
+    }
+
+    let held = LIVE.load(Ordering::Relaxed) - before;
+    drop((store, client));
+    held / events as usize
+}
+
+#[test]
+fn test_memory_is_linear_in_the_events() {
+    let (short, long) = (bytes_per_event(10_000), bytes_per_event(100_000));
+
+    // a snapshot every 100 events would keep 1000 copies of the long history, 50 times as much per event
+    assert!(long < short * 2, "{} bytes per event for 10k events, {} for 100k", short, long);
+}
@@ -0,0 +1,113 @@
+//! Reading a file takes the same memory whatever its size, nothing is kept once sent on.
+//! Live heap bytes are counted by the global allocator, hence a test binary of its own with a single test.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+use drizzly::csv_ingestor::{read_csv, CsvTransaction, IngestionError, IngestionStats, DEFAULT_BATCH_SIZE};
+use drizzly::generator::{generate, GeneratorConfig, OutputFormat};
+use drizzly::parallel_ingestor::{read_csv_parallel, ParallelRead};
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn grow(size: usize) {
+    let live = LIVE.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(live, Ordering::Relaxed);
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// A generated input file, removed once dropped
+struct Input(PathBuf);
+
+impl Input {
+    fn generate(rows: u64) -> Self {
+        let path = std::env::temp_dir().join(format!("drizzly-streaming-{}-{}.csv", std::process::id(), rows));
+        let file = File::create(&path).unwrap();
+        generate(GeneratorConfig::default(), OutputFormat::Csv, None, Some(rows), file, None).unwrap();
+        Input(path)
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Stats of the read and the most heap it took at once, with a consumer dropping every batch as the dispatcher would
+fn peak_while<F>(read: F) -> (IngestionStats, usize)
+where
+    F: FnOnce(SyncSender<Vec<CsvTransaction>>) -> Result<IngestionStats, IngestionError>,
+{
+    let (sender, receiver) = sync_channel(4);
+    let consumer = thread::spawn(move || receiver.into_iter().map(|batch: Vec<CsvTransaction>| batch.len()).sum::<usize>());
+
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let stats = read(sender).unwrap();
+    let peak = PEAK.load(Ordering::Relaxed) - before;
+
+    assert_eq!(consumer.join().unwrap() as u64, stats.rows_read - stats.rows_rejected);
+    (stats, peak)
+}
+
+#[test]
+fn test_memory_does_not_grow_with_the_file() {
+    let (small, large) = (Input::generate(20_000), Input::generate(200_000));
+    let parallel = ParallelRead { parsers: 2, chunk_size: 64 * 1024, mmap: false };
+
+    type Read<'a> = &'a dyn Fn(&str, SyncSender<Vec<CsvTransaction>>) -> Result<IngestionStats, IngestionError>;
+    let readers: [(&str, Read, usize); 3] = [
+        ("sequential", &|path, sender| read_csv(path, sender, None, DEFAULT_BATCH_SIZE), 512 << 10),
+        ("parallel", &|path, sender| read_csv_parallel(path, sender, None, DEFAULT_BATCH_SIZE, parallel), 4 << 20),
+        ("parallel mmap", &|path, sender| read_csv_parallel(path, sender, None, DEFAULT_BATCH_SIZE, ParallelRead { mmap: true, ..parallel }), 2 << 20),
+    ];
+
+    // the same bound for ten times the rows, keeping the large file's rows would take several times more
+    assert!(200_000 * size_of::<CsvTransaction>() > 8 << 20);
+    for (name, read, bound) in readers {
+        for (input, rows) in [(&small, 20_000), (&large, 200_000)] {
+            let (stats, peak) = peak_while(|sender| read(input.path(), sender));
+
+            assert_eq!(stats.rows_read, rows, "{}", name);
+            assert_eq!(stats.bytes, std::fs::metadata(&input.0).unwrap().len(), "{}", name);
+            assert!(peak < bound, "{}: {} bytes at peak reading {} rows", name, peak, rows);
+        }
+    }
+}